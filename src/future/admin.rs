@@ -0,0 +1,156 @@
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http::{Method, Request, Response, StatusCode, header};
+use http_body_util::Full;
+use percent_encoding::percent_decode_str;
+use serde_json::{Value, json};
+use tower_service::Service;
+
+use crate::future::{
+    entry::EntryState,
+    registry::{BoxFuture, CacheInfo, CacheRegistry},
+};
+
+/// An HTTP service to inspect the caches of a `CacheRegistry`, e.g. during incidents.
+///
+/// Caches are added to the registry with
+/// [`RowCacheBuilder::register`](crate::future::RowCacheBuilder::register), which leaves their
+/// capacity untouched, or with
+/// [`RowCacheBuilder::share_budget`](crate::future::RowCacheBuilder::share_budget).
+///
+/// `AdminService` is a `tower::Service`, so it can be mounted in an `axum` router with
+/// `Router::nest_service("/caches", AdminService::new(registry))`, or served by `hyper`.
+/// Caches are addressed by the name set with
+/// [`RowCacheBuilder::name`](crate::future::RowCacheBuilder::name), and keys are parsed with
+/// [`RowCacheBuilder::key_parser`](crate::future::RowCacheBuilder::key_parser). Path segments
+/// are percent-decoded. The routes, relative to where the service is mounted, are:
+///
+/// - `GET /`: lists the registered caches with their stats.
+/// - `GET /{name}`: describes a cache.
+/// - `DELETE /{name}`: discards all the entries of a cache.
+/// - `GET /{name}/{key}`: returns the cached state of a key (`found`, `not_found` for a cached
+///   `None`, or `absent`) and its remaining time before expiry, without its value.
+/// - `DELETE /{name}/{key}`: discards the entry of a key.
+///
+/// Responses are JSON documents, except for the `204 No Content` of the `DELETE` routes.
+#[derive(Clone)]
+pub struct AdminService {
+    registry: Arc<CacheRegistry>,
+}
+
+type Body = Full<Bytes>;
+
+impl AdminService {
+    /// Creates a new `AdminService` for the caches of a registry.
+    pub fn new(registry: Arc<CacheRegistry>) -> Self {
+        AdminService { registry }
+    }
+}
+
+impl<B> Service<Request<B>> for AdminService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response<Body>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let registry = Arc::clone(&self.registry);
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+        Box::pin(async move { Ok(respond(&registry, &method, &path).await) })
+    }
+}
+
+async fn respond(registry: &CacheRegistry, method: &Method, path: &str) -> Response<Body> {
+    let segments: Vec<_> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy())
+        .collect();
+    let (name, key) = match segments.as_slice() {
+        [] if method == Method::GET => {
+            let caches = registry.caches().iter().map(info).collect();
+            return json(StatusCode::OK, Value::Array(caches));
+        }
+        [] => return error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+        [name] => (name, None),
+        [name, key] => (name, Some(key)),
+        _ => return error(StatusCode::NOT_FOUND, "not found"),
+    };
+    let Some(cache) = registry.cache(name) else {
+        return error(StatusCode::NOT_FOUND, &format!("no cache named `{name}`"));
+    };
+    match (method, key) {
+        (&Method::GET, None) => json(StatusCode::OK, info(&cache.info())),
+        (&Method::DELETE, None) => {
+            cache.invalidate_all();
+            no_content()
+        }
+        (&Method::GET, Some(key)) => match cache.inspect(key).await {
+            Some(state) => json(StatusCode::OK, entry(key, state)),
+            None => error(StatusCode::BAD_REQUEST, &format!("invalid key `{key}`")),
+        },
+        (&Method::DELETE, Some(key)) => match cache.invalidate(key).await {
+            true => no_content(),
+            false => error(StatusCode::BAD_REQUEST, &format!("invalid key `{key}`")),
+        },
+        _ => error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+    }
+}
+
+fn info(info: &CacheInfo) -> Value {
+    json!({
+        "name": info.name,
+        "priority": info.priority,
+        "capacity": info.capacity,
+        "usage": info.usage,
+        "stats": {
+            "hits": info.stats.hits,
+            "misses": info.stats.misses,
+            "filtered": info.stats.filtered,
+            "hit_ratio": info.stats.hit_ratio(),
+            "entry_count": info.stats.entry_count,
+            "weighted_size": info.stats.weighted_size,
+        },
+    })
+}
+
+fn entry(key: &str, state: EntryState<()>) -> Value {
+    let (state, expires_in) = match state {
+        EntryState::Absent => ("absent", None),
+        EntryState::Found { expires_in, .. } => ("found", expires_in),
+        EntryState::NotFound { expires_in } => ("not_found", expires_in),
+    };
+    json!({
+        "key": key,
+        "state": state,
+        "expires_in_ms": expires_in.map(|d| d.as_millis() as u64),
+    })
+}
+
+fn json(status: StatusCode, value: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::from(value.to_string()))
+        .unwrap()
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, json!({ "error": message }))
+}
+
+fn no_content() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Full::default())
+        .unwrap()
+}
@@ -0,0 +1,191 @@
+use std::{
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+use sqlx::{Database, pool::PoolConnection};
+
+use crate::future::registry::BoxFuture;
+
+/// Tries to acquire the advisory lock of a key on a connection, without waiting for it.
+/// Returns `false` if the lock is held by another connection.
+type Acquire<DB> = Box<
+    dyn for<'c> Fn(&'c mut <DB as Database>::Connection, i64) -> BoxFuture<'c, sqlx::Result<bool>>
+        + Send
+        + Sync,
+>;
+
+/// Releases the advisory lock of a key acquired on a connection.
+type Release<DB> = Box<
+    dyn for<'c> Fn(&'c mut <DB as Database>::Connection, i64) -> BoxFuture<'c, sqlx::Result<()>>
+        + Send
+        + Sync,
+>;
+
+/// The advisory lock taken by a `RowCache` around the query of a key, as set with
+/// `RowCacheBuilder::advisory_lock`, so that the replicas of the cache query a key one at a
+/// time.
+///
+/// The lock is acquired and released within a transaction of a [`LockingConnection`]. While
+/// it is held by another connection, the load retries every `RETRY_INTERVAL` without keeping
+/// a connection, and queries the key without the lock once `timeout` has elapsed.
+pub(crate) struct AdvisoryLock<DB: Database> {
+    pub(crate) acquire: Acquire<DB>,
+    pub(crate) release: Release<DB>,
+    pub(crate) timeout: Duration,
+}
+
+/// How long a load waits before trying again to acquire a lock held by another connection.
+pub(crate) const RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+impl<DB: Database> AdvisoryLock<DB> {
+    /// Returns the ID of the lock of a key of a cache, which must be the same in all the
+    /// replicas of the cache.
+    ///
+    /// The ID is the 64-bit FNV-1a hash of the name of the cache and of the key, as written by
+    /// its `Hash` implementation, so it does not depend on the platform or the toolchain for
+    /// keys whose `Hash` implementations do not either, like integers and strings.
+    pub(crate) fn id(cache: Option<&str>, key: &impl Hash) -> i64 {
+        let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+        hasher.write(cache.unwrap_or_default().as_bytes());
+        hasher.write_u8(0xff);
+        key.hash(&mut hasher);
+        hasher.finish() as i64
+    }
+}
+
+/// A 64-bit FNV-1a hasher writing integers in little-endian order, and `usize`s as `u64`s.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// A pooled connection taking an advisory lock, closed rather than returned to the pool if it
+/// is dropped before the lock is released, e.g. when the load is cancelled, so that an idle
+/// connection does not keep holding the lock.
+pub(crate) struct LockingConnection<DB: Database> {
+    conn: PoolConnection<DB>,
+    released: bool,
+}
+
+impl<DB: Database> LockingConnection<DB> {
+    pub(crate) fn new(conn: PoolConnection<DB>) -> Self {
+        LockingConnection {
+            conn,
+            released: false,
+        }
+    }
+
+    /// Returns the connection to the pool when dropped, once the lock is released.
+    pub(crate) fn released(mut self) {
+        self.released = true;
+    }
+}
+
+impl<DB: Database> Deref for LockingConnection<DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl<DB: Database> DerefMut for LockingConnection<DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+impl<DB: Database> Drop for LockingConnection<DB> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.conn.close_on_drop();
+        }
+    }
+}
+
+/// Takes a transaction-level advisory lock, released when the transaction ends.
+#[cfg(feature = "postgres")]
+pub(crate) fn postgres(timeout: Duration) -> AdvisoryLock<sqlx::Postgres> {
+    AdvisoryLock {
+        acquire: Box::new(|conn, id| Box::pin(postgres::acquire(conn, id))),
+        release: Box::new(|_, _| Box::pin(async { Ok(()) })),
+        timeout,
+    }
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use sqlx::PgConnection;
+
+    /// Tries to take the lock within the transaction of the connection.
+    pub(super) async fn acquire(conn: &mut PgConnection, id: i64) -> sqlx::Result<bool> {
+        sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1)")
+            .bind(id)
+            .fetch_one(conn)
+            .await
+    }
+}
+
+/// Takes a named lock, released explicitly after the query.
+#[cfg(feature = "mysql")]
+pub(crate) fn mysql(timeout: Duration) -> AdvisoryLock<sqlx::MySql> {
+    AdvisoryLock {
+        acquire: Box::new(|conn, id| Box::pin(mysql::acquire(conn, id))),
+        release: Box::new(|conn, id| Box::pin(mysql::release(conn, id))),
+        timeout,
+    }
+}
+
+#[cfg(feature = "mysql")]
+mod mysql {
+    use sqlx::{Executor, MySqlConnection, Row};
+
+    /// Tries to take the lock, with a timeout of 0 so that `GET_LOCK` does not wait.
+    pub(super) async fn acquire(conn: &mut MySqlConnection, id: i64) -> sqlx::Result<bool> {
+        let lock = sqlx::query("SELECT GET_LOCK(?, 0)").bind(lock(id));
+        let locked: Option<i64> = conn.fetch_one(lock).await?.try_get(0)?;
+        Ok(locked == Some(1))
+    }
+
+    pub(super) async fn release(conn: &mut MySqlConnection, id: i64) -> sqlx::Result<()> {
+        conn.execute(sqlx::query("SELECT RELEASE_LOCK(?)").bind(lock(id)))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the name of the lock of an ID.
+    fn lock(id: i64) -> String {
+        format!("row_cache:{id:016x}")
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// The smallest number of keys a filter is sized for.
+const MIN_KEYS: usize = 1024;
+
+/// The maximum number of bits set per key.
+const MAX_HASHES: u32 = 16;
+
+/// The existence filter of a `RowCache`, as set with `RowCacheBuilder::existence_filter`.
+///
+/// The filter is a Bloom filter of the keys of the rows of the table, built from a scan of
+/// the keys and extended with the keys of the rows loaded, inserted or changed since. Keys
+/// it does not contain are definitely absent from the table, while keys it contains are
+/// present or false positives. A filter whose keys cannot be known anymore, e.g. after an
+/// unknown set of rows changed, contains every key until it is rebuilt.
+pub(crate) struct ExistenceFilter {
+    /// The query scanning the keys of the table.
+    pub(crate) scan: Box<str>,
+    rate: f64,
+    hasher: RandomState,
+    /// The filter, if it was built and is still trusted.
+    current: RwLock<Option<Arc<Bloom>>>,
+    /// The hashes of the keys added during a rebuild, if one is in progress.
+    pending: Mutex<Option<Vec<u64>>>,
+    /// Serializes the rebuilds.
+    pub(crate) rebuild: tokio::sync::Mutex<()>,
+}
+
+impl ExistenceFilter {
+    pub(crate) fn new(scan: String, rate: f64) -> Self {
+        ExistenceFilter {
+            scan: scan.into(),
+            rate: rate.clamp(f64::MIN_POSITIVE, 0.5),
+            hasher: RandomState::new(),
+            current: RwLock::default(),
+            pending: Mutex::default(),
+            rebuild: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// Returns whether a key may exist, i.e. `false` only if it is definitely absent.
+    pub(crate) fn may_contain<Q: Hash + ?Sized>(&self, key: &Q) -> bool {
+        let current = self.current.read().unwrap();
+        current
+            .as_ref()
+            .is_none_or(|bloom| bloom.contains(self.hasher.hash_one(key)))
+    }
+
+    /// Adds a key whose row may exist.
+    pub(crate) fn insert<Q: Hash + ?Sized>(&self, key: &Q) {
+        let hash = self.hasher.hash_one(key);
+        if let Some(pending) = &mut *self.pending.lock().unwrap() {
+            pending.push(hash);
+        }
+        if let Some(bloom) = &*self.current.read().unwrap() {
+            bloom.insert(hash);
+        }
+    }
+
+    /// Stops trusting the filter until it is rebuilt, e.g. after rows whose keys are unknown
+    /// were inserted. A rebuild in progress is discarded, as its scan may predate the rows.
+    pub(crate) fn reset(&self) {
+        *self.pending.lock().unwrap() = None;
+        *self.current.write().unwrap() = None;
+    }
+
+    /// Starts recording the keys added while the keys of the table are scanned.
+    pub(crate) fn start(&self) {
+        *self.pending.lock().unwrap() = Some(Vec::new());
+    }
+
+    /// Replaces the filter with one built from the scanned keys and the keys added since the
+    /// scan started. Returns `false` if the rebuild was discarded by a reset.
+    pub(crate) fn finish<Q: Hash>(&self, keys: &[Q]) -> bool {
+        let Some(pending) = self.pending.lock().unwrap().as_mut().map(std::mem::take) else {
+            return false;
+        };
+        let bloom = Bloom::new(keys.len().saturating_mul(2).max(MIN_KEYS), self.rate);
+        for hash in keys.iter().map(|key| self.hasher.hash_one(key)) {
+            bloom.insert(hash);
+        }
+        for hash in pending {
+            bloom.insert(hash);
+        }
+        // the keys added while the filter was built are read once the filter is locked, so
+        // that the keys added afterwards are added to the new filter
+        let mut current = self.current.write().unwrap();
+        let Some(pending) = self.pending.lock().unwrap().take() else {
+            return false;
+        };
+        for hash in pending {
+            bloom.insert(hash);
+        }
+        *current = Some(Arc::new(bloom));
+        true
+    }
+}
+
+/// A Bloom filter of the hashes of keys, addressed by double hashing.
+struct Bloom {
+    bits: Box<[AtomicU64]>,
+    hashes: u32,
+}
+
+impl Bloom {
+    /// Creates a filter sized for `keys` keys with a false-positive rate of `rate`.
+    fn new(keys: usize, rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(keys as f64) * rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let words = bits.div_ceil(64).max(1);
+        let hashes = ((words * 64) as f64 / keys as f64 * ln2).round() as u32;
+        Bloom {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hashes: hashes.clamp(1, MAX_HASHES),
+        }
+    }
+
+    /// Returns the indices of the bits of a hash.
+    fn indices(&self, hash: u64) -> impl Iterator<Item = usize> + use<> {
+        let len = self.bits.len() as u64 * 64;
+        let step = hash.rotate_left(32) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+    }
+
+    fn insert(&self, hash: u64) {
+        for index in self.indices(hash) {
+            self.bits[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+        }
+    }
+
+    fn contains(&self, hash: u64) -> bool {
+        self.indices(hash)
+            .all(|index| self.bits[index / 64].load(Ordering::Relaxed) & (1 << (index % 64)) != 0)
+    }
+}
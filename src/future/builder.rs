@@ -5,6 +5,8 @@ use std::{
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "deepsize")]
+use deepsize::DeepSizeOf;
 use moka::{
     Expiry,
     future::{Cache, CacheBuilder},
@@ -12,14 +14,40 @@ use moka::{
     policy::EvictionPolicy,
 };
 use send_sync_static::SSS;
-use sqlx::{Database, Pool};
+use sqlx::{
+    Arguments, ColumnIndex, Database, Decode, Encode, Executor, IntoArguments, Pool, Type,
+    error::BoxDynError,
+};
 
-use crate::future::cache::RowCache;
+#[cfg(feature = "sqlite")]
+use crate::future::UpdateHook;
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+use crate::future::advisory;
+use crate::future::{
+    advisory::AdvisoryLock,
+    bloom::ExistenceFilter,
+    bus::{BusLink, InvalidationBus},
+    cache::RowCache,
+    changes::{Change, Changes},
+    dependents::Dependents,
+    entry::{BoxExpiry, Probed},
+    error::BuildError,
+    events::Events,
+    expiry::{BackoffExpiry, ExpiryOptions},
+    generation::Generation,
+    limit::LoadLimiter,
+    log::{self, InvalidationLog, LogTail},
+    registry::{self, CacheRegistry, Inspector, ParseKey, Share},
+    schema::table_columns,
+    stats::Counters,
+    tags::{Tags, TagsOf},
+};
 
 /// Defines the capabilities for a database to construct SQL queries.
 ///
 /// This trait provides constants for quoting identifiers and placeholders,
-/// which are essential for building database-agnostic SQL queries.
+/// which are essential for building database-agnostic SQL queries, along with
+/// helpers to quote identifiers safely.
 pub trait QueryBuilder {
     /// The character used to quote database identifiers (e.g., table names, column names).
     ///
@@ -29,6 +57,253 @@ pub trait QueryBuilder {
     ///
     /// For example, `?` for SQLite/MySQL, or `$1` for PostgreSQL.
     const PLACEHOLDER: &str;
+    /// A query returning the names of the columns of a table, in order.
+    ///
+    /// The query takes the schema of the table (or `NULL` for the current schema) and the name
+    /// of the table as its parameters, and returns no row if the table does not exist.
+    const TABLE_COLUMNS: &str;
+
+    /// Returns the placeholder of the `index`-th parameter of a query, starting from 1.
+    ///
+    /// Defaults to [`QueryBuilder::PLACEHOLDER`] for databases with positional placeholders.
+    /// Databases with numbered placeholders (e.g., `$2` on PostgreSQL) override it.
+    fn placeholder(index: usize) -> String {
+        let _ = index;
+        Self::PLACEHOLDER.to_owned()
+    }
+
+    /// Quotes an identifier, doubling the quote characters it contains.
+    ///
+    /// For example, `we"ird` is quoted as `"we""ird"` on PostgreSQL/SQLite.
+    fn quote(ident: &str) -> String {
+        let escaped = ident.replace(Self::QUOTE, &Self::QUOTE.repeat(2));
+        format!("{0}{1}{0}", Self::QUOTE, escaped)
+    }
+
+    /// Quotes a table name that is optionally qualified by a schema (or a database on MySQL),
+    /// quoting both parts separately.
+    ///
+    /// For example, `public.users` is quoted as `"public"."users"` on PostgreSQL.
+    fn quote_table(table: &str) -> String {
+        match split_table(table) {
+            (Some(schema), table) => format!("{}.{}", Self::quote(schema), Self::quote(table)),
+            (None, table) => Self::quote(table),
+        }
+    }
+
+    /// Returns the dialect of the queries sent through a pool.
+    ///
+    /// Defaults to the dialect described by this trait. Drivers connecting to a database
+    /// chosen at runtime, like `sqlx::Any`, resolve it from the pool instead.
+    fn dialect(pool: &Pool<Self>) -> Dialect
+    where
+        Self: Database + Sized,
+    {
+        let _ = pool;
+        Dialect::of::<Self>()
+    }
+
+    /// Returns the statement creating the table of an [`InvalidationLog`], if it does not
+    /// exist.
+    ///
+    /// Defaults to the syntax of SQLite.
+    fn create_log(log: &str) -> String
+    where
+        Self: Sized,
+    {
+        log::sqlite::create_log::<Self>(log)
+    }
+
+    /// Returns the statements creating the triggers logging the changes of the rows of a table
+    /// into an [`InvalidationLog`].
+    ///
+    /// Defaults to the syntax of SQLite.
+    fn log_triggers(log: &str, table: &str, key: &str) -> Vec<String>
+    where
+        Self: Sized,
+    {
+        log::sqlite::log_triggers::<Self>(log, table, key)
+    }
+}
+
+/// The quoting and placeholder strategy of a database, dispatched at runtime.
+///
+/// A `Dialect` captures the helpers of a `QueryBuilder` so that queries can be generated
+/// for a database that is only known once a pool is created.
+#[derive(Clone, Copy)]
+pub struct Dialect {
+    quote: fn(&str) -> String,
+    quote_table: fn(&str) -> String,
+    placeholder: fn(usize) -> String,
+    table_columns: &'static str,
+    create_log: fn(&str) -> String,
+    log_triggers: fn(&str, &str, &str) -> Vec<String>,
+}
+
+impl Dialect {
+    /// Returns the dialect described by a `QueryBuilder`.
+    pub fn of<QB: QueryBuilder>() -> Self {
+        Dialect {
+            quote: QB::quote,
+            quote_table: QB::quote_table,
+            placeholder: QB::placeholder,
+            table_columns: QB::TABLE_COLUMNS,
+            create_log: QB::create_log,
+            log_triggers: QB::log_triggers,
+        }
+    }
+
+    /// See [`QueryBuilder::quote`].
+    pub fn quote(&self, ident: &str) -> String {
+        (self.quote)(ident)
+    }
+
+    /// See [`QueryBuilder::quote_table`].
+    pub fn quote_table(&self, table: &str) -> String {
+        (self.quote_table)(table)
+    }
+
+    /// See [`QueryBuilder::placeholder`].
+    pub fn placeholder(&self, index: usize) -> String {
+        (self.placeholder)(index)
+    }
+
+    /// See [`QueryBuilder::TABLE_COLUMNS`].
+    pub fn table_columns(&self) -> &'static str {
+        self.table_columns
+    }
+
+    /// See [`QueryBuilder::create_log`].
+    pub fn create_log(&self, log: &str) -> String {
+        (self.create_log)(log)
+    }
+
+    /// See [`QueryBuilder::log_triggers`].
+    pub fn log_triggers(&self, log: &str, table: &str, key: &str) -> Vec<String> {
+        (self.log_triggers)(log, table, key)
+    }
+}
+
+/// Splits a table name into its optional schema and its unqualified name at the last dot.
+pub(crate) fn split_table(table: &str) -> (Option<&str>, &str) {
+    match table.rsplit_once('.') {
+        Some((schema, table)) => (Some(schema), table),
+        None => (None, table),
+    }
+}
+
+/// Binds a value to the arguments of a query, after the key.
+pub(crate) type Bind<DB> = Box<
+    dyn for<'q> Fn(&mut <DB as Database>::Arguments<'q>) -> Result<(), BoxDynError> + Send + Sync,
+>;
+
+/// Binds a key to the arguments of a query.
+pub(crate) type BindKey<DB, K> = Box<
+    dyn for<'q> Fn(&K, &mut <DB as Database>::Arguments<'q>) -> Result<(), BoxDynError>
+        + Send
+        + Sync,
+>;
+
+/// Maps a key to the value bound to the query.
+type MapKey<K, B> = Box<dyn Fn(&K) -> B + Send + Sync>;
+
+/// Converts a row fetched from the database to the value stored in the cache.
+pub(crate) type MapRow<V, W> = Box<dyn Fn(V) -> Result<W, BoxDynError> + Send + Sync>;
+
+/// Weighs an entry of the cache.
+type Weigher<K, W> = Box<WeigherFn<K, W>>;
+
+type WeigherFn<K, W> = dyn Fn(&K, &Option<W>) -> u32 + Send + Sync;
+
+/// Listens to the removals of the entries of the cache.
+type Listener<K, W> = Box<dyn Fn(Arc<K>, Option<W>, RemovalCause) -> ListenerFuture + Send + Sync>;
+
+/// The table a `RowCacheBuilder` generates its query for.
+struct Table {
+    dialect: Dialect,
+    name: Box<str>,
+    id: Box<str>,
+    columns: Option<Vec<Box<str>>>,
+    filters: Vec<Filter>,
+}
+
+/// A predicate appended to the `WHERE` clause of the query generated for a table.
+enum Filter {
+    /// Compares a column to a value bound to the query, e.g. `"tenant" = $2`.
+    Compare {
+        column: Box<str>,
+        operator: &'static str,
+    },
+    /// Checks if a column is null, e.g. `"deleted_at" IS NULL`.
+    Null { column: Box<str>, negated: bool },
+}
+
+impl Table {
+    /// Renders the `SELECT` query of the table.
+    fn select(&self) -> String {
+        let dialect = &self.dialect;
+        let mut predicates = vec![format!(
+            "{} = {}",
+            dialect.quote(&self.id),
+            dialect.placeholder(1)
+        )];
+        predicates.extend(self.predicates(1));
+        let columns = match &self.columns {
+            Some(columns) => columns
+                .iter()
+                .map(|column| dialect.quote(column))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "*".to_owned(),
+        };
+        format!(
+            "SELECT {} FROM {} WHERE {}",
+            columns,
+            dialect.quote_table(&self.name),
+            predicates.join(" AND ")
+        )
+    }
+
+    /// Renders the query scanning the keys of the cached rows of the table.
+    fn scan(&self) -> String {
+        let dialect = &self.dialect;
+        let predicates = self.predicates(0);
+        let mut scan = format!(
+            "SELECT {} FROM {}",
+            dialect.quote(&self.id),
+            dialect.quote_table(&self.name)
+        );
+        if !predicates.is_empty() {
+            scan = format!("{scan} WHERE {}", predicates.join(" AND "));
+        }
+        scan
+    }
+
+    /// Renders the predicates of the filters, numbering their placeholders after the first
+    /// `index` ones.
+    fn predicates(&self, index: usize) -> Vec<String> {
+        let dialect = &self.dialect;
+        let mut index = index;
+        let mut predicates = Vec::new();
+        for filter in &self.filters {
+            predicates.push(match filter {
+                Filter::Compare { column, operator } => {
+                    index += 1;
+                    format!(
+                        "{} {} {}",
+                        dialect.quote(column),
+                        operator,
+                        dialect.placeholder(index)
+                    )
+                }
+                Filter::Null { column, negated } => {
+                    let not = if *negated { " NOT" } else { "" };
+                    format!("{} IS{} NULL", dialect.quote(column), not)
+                }
+            });
+        }
+        predicates
+    }
 }
 
 /// A builder for creating and configuring a `RowCache`.
@@ -45,9 +320,35 @@ pub trait QueryBuilder {
 /// - **Standard Moka Cache Features**: Exposes most other
 ///   `moka::future::CacheBuilder` functionalities like capacity limits and
 ///   eviction listeners.
-pub struct RowCacheBuilder<DB: Database, K, V, W> {
+///
+/// The `B` type parameter is the type of the value bound to the query for a key. It defaults
+/// to `K` and can be changed with [`RowCacheBuilder::map_key`].
+pub struct RowCacheBuilder<DB: Database, K, V, W, B = K> {
     inner: CacheBuilder<K, Option<W>, Cache<K, Option<W>>>,
     query: Box<str>,
+    table: Option<Table>,
+    binds: Vec<Bind<DB>>,
+    map_key: MapKey<K, B>,
+    /// Whether the keys are mapped with [`RowCacheBuilder::map_key`].
+    mapped_key: bool,
+    map_row: MapRow<V, W>,
+    weigher: Option<Weigher<K, W>>,
+    expiry: BoxExpiry<K, W>,
+    expiry_options: ExpiryOptions,
+    listener: Option<Listener<K, W>>,
+    event_capacity: usize,
+    tags: Option<TagsOf<W>>,
+    generation_source: Option<Box<str>>,
+    changes: Arc<Changes<K>>,
+    invalidation_log: Option<InvalidationLog>,
+    bus: Option<BusLink<K>>,
+    advisory_lock: Option<AdvisoryLock<DB>>,
+    existence_filter: Option<f64>,
+    max_loads: Option<usize>,
+    max_queued_loads: Option<usize>,
+    /// The registry of the cache, along with its priority if it shares the budget.
+    registry: Option<(Arc<CacheRegistry>, Option<u32>)>,
+    parse_key: Option<ParseKey<K>>,
     pool: Pool<DB>,
     _0: PhantomData<(DB, V)>,
 }
@@ -68,7 +369,8 @@ where
     /// # Arguments
     /// * `max_capacity` - The maximum number of entries the cache can hold.
     /// * `pool` - The SQLx database connection pool.
-    /// * `table` - The name of the database table to cache rows from.
+    /// * `table` - The name of the database table to cache rows from, optionally qualified
+    ///   by its schema (e.g., "public.users"). Identifiers are quoted and escaped.
     pub fn new(max_capacity: u64, pool: Pool<DB>, table: &str) -> Self
     where
         DB: QueryBuilder,
@@ -84,20 +386,23 @@ where
     /// # Arguments
     /// * `max_capacity` - The maximum number of entries the cache can hold.
     /// * `pool` - The SQLx database connection pool.
-    /// * `table` - The name of the database table to cache rows from.
+    /// * `table` - The name of the database table to cache rows from, optionally qualified
+    ///   by its schema (e.g., "public.users"). Identifiers are quoted and escaped.
     /// * `id` - The name of the primary key column for the table (e.g., "user_id", "product_uuid").
     pub fn for_table(max_capacity: u64, pool: Pool<DB>, table: &str, id: &str) -> Self
     where
         DB: QueryBuilder,
     {
-        let query = format!(
-            "SELECT * FROM {2}{0}{2} WHERE {2}{1}{2} = {3}",
-            table,
-            id,
-            DB::QUOTE,
-            DB::PLACEHOLDER
-        );
-        Self::for_query(max_capacity, pool, query)
+        let table = Table {
+            dialect: DB::dialect(&pool),
+            name: table.into(),
+            id: id.into(),
+            columns: None,
+            filters: Vec::new(),
+        };
+        let mut builder = Self::for_query(max_capacity, pool, table.select());
+        builder.table = Some(table);
+        builder
     }
 
     /// Creates a new `RowCacheBuilder` with a specified maximum capacity, database pool,
@@ -114,14 +419,427 @@ where
     /// * `query` - The custom SQL query string. It should contain a single placeholder
     ///   for the key (e.g., `SELECT * FROM users WHERE id = $1` for PostgreSQL,
     ///   or `SELECT * FROM users WHERE id = ?` for MySQL/SQLite).
+    #[allow(clippy::redundant_field_names)]
     pub fn for_query(max_capacity: u64, pool: Pool<DB>, query: impl Into<Box<str>>) -> Self {
         RowCacheBuilder {
-            inner: CacheBuilder::new(max_capacity).expire_after(DefaultExpiry::default()),
+            inner: CacheBuilder::new(max_capacity),
             query: query.into(),
+            table: None,
+            binds: Vec::new(),
+            map_key: Box::new(K::clone),
+            mapped_key: false,
+            map_row: Box::new(|row| Ok(W::from(row))),
+            weigher: None,
+            expiry: Box::new(DefaultExpiry::default()),
+            expiry_options: ExpiryOptions::default(),
+            listener: None,
+            event_capacity: 64,
+            tags: None,
+            generation_source: None,
+            changes: Arc::default(),
+            invalidation_log: None,
+            bus: None,
+            advisory_lock: None,
+            existence_filter: None,
+            max_loads: None,
+            max_queued_loads: None,
+            registry: None,
+            parse_key: None,
             pool: pool,
             _0: PhantomData,
         }
     }
+}
+
+impl<DB, K, V, W, B> RowCacheBuilder<DB, K, V, W, B>
+where
+    DB: Database,
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Maps the keys of the cache to the values bound to the query.
+    ///
+    /// By default, the key itself is bound to the query, which requires `K` to be encodable
+    /// by the database. With a mapping, any `Hash + Eq` type can be used as the key, such as
+    /// a newtype ID (`UserId(Uuid)`) whose inner value is bound. The mapping is applied on
+    /// every load, so lookups and queries always agree on the key.
+    ///
+    /// # Arguments
+    /// * `map` - Maps a key to the value bound to the query, e.g. `|id: &UserId| id.0`.
+    pub fn map_key<B2>(
+        self,
+        map: impl Fn(&K) -> B2 + Send + Sync + 'static,
+    ) -> RowCacheBuilder<DB, K, V, W, B2> {
+        RowCacheBuilder {
+            inner: self.inner,
+            query: self.query,
+            table: self.table,
+            binds: self.binds,
+            map_key: Box::new(map),
+            mapped_key: true,
+            map_row: self.map_row,
+            weigher: self.weigher,
+            expiry: self.expiry,
+            expiry_options: self.expiry_options,
+            listener: self.listener,
+            event_capacity: self.event_capacity,
+            tags: self.tags,
+            generation_source: self.generation_source,
+            changes: self.changes,
+            invalidation_log: self.invalidation_log,
+            bus: self.bus,
+            advisory_lock: self.advisory_lock,
+            existence_filter: self.existence_filter,
+            max_loads: self.max_loads,
+            max_queued_loads: self.max_queued_loads,
+            registry: self.registry,
+            parse_key: self.parse_key,
+            pool: self.pool,
+            _0: PhantomData,
+        }
+    }
+
+    /// Converts the rows fetched from the database before they are cached.
+    ///
+    /// By default, a row `V` is converted to the cached value `W` with `W::from`. This method
+    /// prepends a fallible conversion from another row type `R`, which becomes the type
+    /// decoded from the database. It can drop sensitive columns, precompute derived fields or
+    /// decompress a column without wrapping the row in a newtype. For example, a
+    /// `PgCacheBuilder<i64, UserView>` mapped with `.map_row(|row: User| UserView::try_from(row))`
+    /// fetches `User`s and caches `Arc<UserView>`s.
+    ///
+    /// A failed conversion is returned by the lookup as a `sqlx::Error::Decode` and nothing
+    /// is cached. The weigher and the eviction listeners see the converted value.
+    ///
+    /// # Arguments
+    /// * `map` - Converts a fetched row `R` to the row type `V` of the builder.
+    pub fn map_row<R, E>(
+        self,
+        map: impl Fn(R) -> Result<V, E> + Send + Sync + 'static,
+    ) -> RowCacheBuilder<DB, K, R, W, B>
+    where
+        R: Unpin + SSS,
+        E: Into<BoxDynError>,
+    {
+        let map_row = self.map_row;
+        RowCacheBuilder {
+            inner: self.inner,
+            query: self.query,
+            table: self.table,
+            binds: self.binds,
+            map_key: self.map_key,
+            mapped_key: self.mapped_key,
+            map_row: Box::new(move |row| map_row(map(row).map_err(Into::into)?)),
+            weigher: self.weigher,
+            expiry: self.expiry,
+            expiry_options: self.expiry_options,
+            listener: self.listener,
+            event_capacity: self.event_capacity,
+            tags: self.tags,
+            generation_source: self.generation_source,
+            changes: self.changes,
+            invalidation_log: self.invalidation_log,
+            bus: self.bus,
+            advisory_lock: self.advisory_lock,
+            existence_filter: self.existence_filter,
+            max_loads: self.max_loads,
+            max_queued_loads: self.max_queued_loads,
+            registry: self.registry,
+            parse_key: self.parse_key,
+            pool: self.pool,
+            _0: PhantomData,
+        }
+    }
+
+    /// Sets the columns selected by the query generated for the table, replacing `*`.
+    ///
+    /// Selecting only the columns decoded by `V` saves bandwidth on unused columns and keeps
+    /// decoding working when columns of unsupported types are added to the table.
+    ///
+    /// This has no effect on builders created with [`RowCacheBuilder::for_query`].
+    ///
+    /// # Arguments
+    /// * `columns` - The names of the columns to select.
+    pub fn columns<C: AsRef<str>>(self, columns: impl IntoIterator<Item = C>) -> Self {
+        let mut builder = self;
+        if let Some(table) = &mut builder.table {
+            table.columns = Some(columns.into_iter().map(|c| c.as_ref().into()).collect());
+            builder.query = table.select().into();
+        }
+        builder
+    }
+
+    /// Appends a `{column} = {value}` predicate to the query generated for the table.
+    ///
+    /// The value is bound to the query as a parameter, after the key. For example,
+    /// `.filter_eq("tenant", "acme".to_owned())` only caches the rows of the `acme` tenant.
+    ///
+    /// This has no effect on builders created with [`RowCacheBuilder::for_query`].
+    ///
+    /// # Arguments
+    /// * `column` - The name of the column to compare.
+    /// * `value` - The value the column must be equal to.
+    pub fn filter_eq<T>(self, column: &str, value: T) -> Self
+    where
+        T: Type<DB> + for<'q> Encode<'q, DB> + Clone + SSS,
+    {
+        self.filter_by(column, "=", value)
+    }
+
+    /// Appends a `{column} <> {value}` predicate to the query generated for the table.
+    ///
+    /// See [`RowCacheBuilder::filter_eq`].
+    pub fn filter_ne<T>(self, column: &str, value: T) -> Self
+    where
+        T: Type<DB> + for<'q> Encode<'q, DB> + Clone + SSS,
+    {
+        self.filter_by(column, "<>", value)
+    }
+
+    /// Appends a `{column} IS NULL` predicate to the query generated for the table.
+    ///
+    /// For example, `.filter_null("deleted_at")` only caches rows that are not soft-deleted.
+    ///
+    /// This has no effect on builders created with [`RowCacheBuilder::for_query`].
+    pub fn filter_null(self, column: &str) -> Self {
+        let column = column.into();
+        self.filter(
+            Filter::Null {
+                column,
+                negated: false,
+            },
+            None,
+        )
+    }
+
+    /// Appends a `{column} IS NOT NULL` predicate to the query generated for the table.
+    ///
+    /// See [`RowCacheBuilder::filter_null`].
+    pub fn filter_not_null(self, column: &str) -> Self {
+        let column = column.into();
+        self.filter(
+            Filter::Null {
+                column,
+                negated: true,
+            },
+            None,
+        )
+    }
+
+    fn filter_by<T>(self, column: &str, operator: &'static str, value: T) -> Self
+    where
+        T: Type<DB> + for<'q> Encode<'q, DB> + Clone + SSS,
+    {
+        let column = column.into();
+        let bind: Bind<DB> = Box::new(move |args| args.add(value.clone()));
+        self.filter(Filter::Compare { column, operator }, Some(bind))
+    }
+
+    fn filter(self, filter: Filter, bind: Option<Bind<DB>>) -> Self {
+        let mut builder = self;
+        if let Some(table) = &mut builder.table {
+            table.filters.push(filter);
+            builder.binds.extend(bind);
+            builder.query = table.select().into();
+        }
+        builder
+    }
+
+    /// Sets the weigher of the entries.
+    ///
+    /// See [`moka::future::CacheBuilder::weigher`].
+    pub fn weigher(self, weigher: impl Fn(&K, &Option<W>) -> u32 + Send + Sync + 'static) -> Self {
+        let mut builder = self;
+        builder.weigher = Some(Box::new(weigher));
+        builder
+    }
+
+    /// Registers the cache in a `CacheRegistry`, so that it can be listed and inspected, e.g.
+    /// by an `AdminService`, by the name set with [`RowCacheBuilder::name`].
+    ///
+    /// The cache keeps its own max capacity and weigher. To share the budget of the registry
+    /// instead, see [`RowCacheBuilder::share_budget`]. The cache leaves the registry when it
+    /// is dropped.
+    ///
+    /// # Arguments
+    /// * `registry` - The registry to join.
+    pub fn register(self, registry: &Arc<CacheRegistry>) -> Self {
+        let mut builder = self;
+        builder.registry = Some((registry.clone(), None));
+        builder
+    }
+
+    /// Registers the cache in a `CacheRegistry`, like [`RowCacheBuilder::register`], sharing
+    /// the budget of the registry with the other caches sharing it instead of using its own
+    /// max capacity.
+    ///
+    /// The share of the cache is proportional to its priority, and is adjusted by
+    /// [`CacheRegistry::rebalance`] according to its hit ratio, while [`CacheRegistry::trim`]
+    /// evicts its entries beyond its share. The cache leaves the registry when it is dropped.
+    ///
+    /// Every cache is guaranteed a tenth of an even split of the budget. A cache of priority 0
+    /// registered next to caches of higher priorities only gets that guaranteed share.
+    ///
+    /// # Arguments
+    /// * `registry` - The registry to join.
+    /// * `priority` - The weight of the cache when the budget is split.
+    pub fn share_budget(self, registry: &Arc<CacheRegistry>, priority: u32) -> Self {
+        let mut builder = self;
+        builder.registry = Some((registry.clone(), Some(priority)));
+        builder
+    }
+
+    /// Sets the parser of the keys given to the admin endpoint of the `CacheRegistry` the cache
+    /// is registered in, enabling the lookup and invalidation of single keys. The keys read
+    /// from an [`InvalidationLog`] are parsed with it too.
+    ///
+    /// # Arguments
+    /// * `parse` - Parses a key, e.g. `|key: &str| key.parse().ok()`.
+    pub fn key_parser(self, parse: impl Fn(&str) -> Option<K> + Send + Sync + 'static) -> Self {
+        let mut builder = self;
+        builder.parse_key = Some(Arc::new(parse));
+        builder
+    }
+
+    /// Sets the number of events a receiver returned by `RowCache::subscribe` can lag behind
+    /// before missing events. Defaults to 64.
+    ///
+    /// See [`tokio::sync::broadcast`] for the behavior of lagging receivers.
+    pub fn event_capacity(self, capacity: usize) -> Self {
+        let mut builder = self;
+        builder.event_capacity = capacity;
+        builder
+    }
+
+    /// Sets a query returning the generation of the cache from a shared source, such as a row
+    /// of the database, e.g. `SELECT generation FROM cache_generations WHERE name = 'users'`.
+    ///
+    /// The query must return a single integer. Whenever `RowCache::sync_generation` sees a new
+    /// value, a new generation starts and the cached entries become stale, so that all the
+    /// replicas of a cache are invalidated by updating the shared source, e.g. after a bulk
+    /// import.
+    ///
+    /// # Arguments
+    /// * `query` - The query returning the generation of the shared source.
+    pub fn generation_source(self, query: impl Into<Box<str>>) -> Self {
+        let mut builder = self;
+        builder.generation_source = Some(query.into());
+        builder
+    }
+
+    /// Tails an `InvalidationLog` with `RowCache::sync_log`, invalidating the entries of the
+    /// rows of the table logged as changed.
+    ///
+    /// The keys are read from the log as text and parsed with the parser set with
+    /// [`RowCacheBuilder::key_parser`]. Without a parser, or if a key cannot be parsed, the
+    /// whole cache is invalidated.
+    ///
+    /// This has no effect on builders created with [`RowCacheBuilder::for_query`].
+    ///
+    /// # Arguments
+    /// * `log` - The log the triggers of the table write to.
+    pub fn invalidation_log(self, log: &InvalidationLog) -> Self {
+        let mut builder = self;
+        builder.invalidation_log = Some(log.clone());
+        builder
+    }
+
+    /// Joins an `InvalidationBus` under a name shared by the replicas of the cache.
+    ///
+    /// The keys inserted with `RowCache::insert`, reloaded with `RowCache::refresh` or
+    /// invalidated with `RowCache::broadcast_invalidate` are formatted with `format_key` and
+    /// sent to the replicas, which invalidate them on their next lookup. The keys received
+    /// from the replicas are parsed with the parser set with [`RowCacheBuilder::key_parser`].
+    /// Without a parser, or if a key cannot be parsed, the whole cache is invalidated.
+    ///
+    /// # Arguments
+    /// * `bus` - The bus to join.
+    /// * `name` - The name of the cache on the bus.
+    /// * `format_key` - Formats a key, e.g. `|id: &i64| id.to_string()`.
+    pub fn invalidation_bus(
+        self,
+        bus: &InvalidationBus,
+        name: &str,
+        format_key: impl Fn(&K) -> String + Send + Sync + 'static,
+    ) -> Self {
+        let mut builder = self;
+        builder.bus = Some(BusLink {
+            bus: bus.clone(),
+            name: name.into(),
+            format_key: Box::new(format_key),
+        });
+        builder
+    }
+
+    /// Tags the cached rows, so that they can be invalidated by tag with
+    /// `RowCache::invalidate_tag`.
+    ///
+    /// The tags of a row are computed when it is cached, and indexed so that invalidating a
+    /// tag does not scan the cache. For example, `.tags(|user: &Arc<User>| [user.org_id])`
+    /// allows the users of an organization to be invalidated with
+    /// `invalidate_tag(&org_id.to_string())`. Cached `None`s have no tags.
+    ///
+    /// The tags must only depend on the value. Values inserted directly into the underlying
+    /// `moka::future::Cache` are not indexed.
+    ///
+    /// # Arguments
+    /// * `tags` - Computes the tags of a cached value.
+    pub fn tags<I>(self, tags: impl Fn(&W) -> I + Send + Sync + 'static) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let mut builder = self;
+        let tags = move |value: &W| tags(value).into_iter().map(|t| t.to_string().into());
+        builder.tags = Some(Box::new(move |value| tags(value).collect()));
+        builder
+    }
+
+    /// Sets the eviction listener of the cache.
+    ///
+    /// See [`moka::future::CacheBuilder::eviction_listener`].
+    pub fn eviction_listener(
+        self,
+        listener: impl Fn(Arc<K>, Option<W>, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.async_eviction_listener(move |key, value, cause| {
+            listener(key, value, cause);
+            Box::pin(async {})
+        })
+    }
+
+    /// Sets the asynchronous eviction listener of the cache.
+    ///
+    /// See [`moka::future::CacheBuilder::async_eviction_listener`].
+    pub fn async_eviction_listener(
+        self,
+        listener: impl Fn(Arc<K>, Option<W>, RemovalCause) -> ListenerFuture + Send + Sync + 'static,
+    ) -> Self {
+        let mut builder = self;
+        builder.listener = Some(Box::new(listener));
+        builder
+    }
+
+    /// Weighs the entries by their approximate memory footprint in bytes, so that
+    /// `max_capacity` becomes a memory budget instead of a number of rows.
+    ///
+    /// The footprint of an entry is the deep size of its key and value, as computed by
+    /// [`DeepSizeOf`](deepsize::DeepSizeOf), which can be derived for row types with
+    /// `#[derive(DeepSizeOf)]`. Weights above `u32::MAX` bytes are saturated.
+    ///
+    /// This replaces any weigher set with [`RowCacheBuilder::weigher`].
+    #[cfg(feature = "deepsize")]
+    pub fn weigh_by_memory(self) -> Self
+    where
+        K: DeepSizeOf,
+        W: DeepSizeOf,
+    {
+        self.weigher(|key, value| {
+            let size = key.deep_size_of() + value.deep_size_of();
+            u32::try_from(size).unwrap_or(u32::MAX)
+        })
+    }
 
     /// Sets the time-to-idle (TTI) expiry for cache entries.
     ///
@@ -157,6 +875,7 @@ where
     pub fn time_to_live(self, duration: Duration) -> Self {
         let mut builder = self;
         builder.inner = builder.inner.time_to_live(duration);
+        builder.expiry_options.ttl = Some(duration);
         builder
     }
 
@@ -170,20 +889,152 @@ where
     /// * `duration` - The duration for which a `None` entry will be cached.
     pub fn time_to_live_for_none(self, duration: Duration) -> Self {
         let mut builder = self;
-        builder.inner = builder.inner.expire_after(DefaultExpiry::new(duration));
+        builder.expiry = Box::new(DefaultExpiry::new(duration));
+        builder
+    }
+
+    /// Caches `None` values for longer each time their key is missing again, replacing
+    /// [`RowCacheBuilder::time_to_live_for_none`].
+    ///
+    /// The first `None` of a key is cached for `initial`, and every consecutive `None` of the
+    /// key for twice as long as the previous one, up to `max`. Keys probed over and over,
+    /// e.g. random IDs, are thus queried less and less often, while keys missing once, e.g.
+    /// rows about to be created, are queried again quickly. A streak ends when a row is
+    /// cached for the key, or when the key has not been missing for twice `max`. The streaks
+    /// of up to 65,536 recently missing keys are remembered.
+    ///
+    /// # Arguments
+    /// * `initial` - The duration for which the first `None` of a key is cached.
+    /// * `max` - The maximum duration for which a `None` is cached.
+    pub fn time_to_live_for_none_backoff(self, initial: Duration, max: Duration) -> Self {
+        let mut builder = self;
+        builder.expiry = Box::new(BackoffExpiry::new(initial, max));
+        builder
+    }
+
+    /// Sets the expiry of the entries, replacing the default one that applies
+    /// [`RowCacheBuilder::time_to_live_for_none`].
+    ///
+    /// See [`moka::future::CacheBuilder::expire_after`].
+    pub fn expire_after(self, expiry: impl Expiry<K, Option<W>> + SSS) -> Self {
+        let mut builder = self;
+        builder.expiry = Box::new(expiry);
+        builder
+    }
+
+    /// Shortens the expiry of every entry by a random fraction of up to `spread`, so that
+    /// entries loaded together, e.g. while warming up, do not all expire together.
+    ///
+    /// The expiry of an entry is the one computed by the expiry of the cache, such as
+    /// [`RowCacheBuilder::time_to_live_for_none`] for `None`s, or the time-to-live of the
+    /// cache otherwise. For example, a spread of `0.2` with a time-to-live of 10 minutes
+    /// expires entries between 8 and 10 minutes after they are cached.
+    ///
+    /// # Arguments
+    /// * `spread` - The maximum fraction of the expiry cut, between 0 and 1.
+    pub fn ttl_jitter(self, spread: f64) -> Self {
+        let mut builder = self;
+        builder.expiry_options.jitter = spread;
+        builder
+    }
+
+    /// Refreshes the entries probabilistically before they expire, following the XFetch
+    /// algorithm, so that hot entries are reloaded by a single lookup instead of missing for
+    /// every concurrent lookup when they expire.
+    ///
+    /// Every lookup of a cached entry (`Some` or `None`) queues its refresh, with a
+    /// probability growing as the entry approaches its expiry. The probability grows sooner
+    /// for caches that are slow to load, as measured by a moving average of the durations of
+    /// their loads. Higher `beta`s refresh earlier, and `1.0` is the usual value. Entries
+    /// without expiry are never refreshed early.
+    ///
+    /// Lookups return the cached value right away, while the queued refreshes are run by
+    /// [`RowCache::run_early_refresh`], which must be spawned on the runtime, and which
+    /// requires the values to implement `PartialEq` so that refreshing an unchanged value
+    /// does not invalidate the entries depending on it.
+    ///
+    /// # Arguments
+    /// * `beta` - The eagerness of the early refreshes.
+    pub fn early_refresh(self, beta: f64) -> Self {
+        let mut builder = self;
+        builder.expiry_options.early_refresh = Some(beta);
+        builder
+    }
+
+    /// Guards the table with an existence filter, a Bloom filter of the keys of its rows,
+    /// so that lookups of keys that are definitely absent return `None` without querying the
+    /// database nor caching the `None`. Probes of random keys then cannot flood the database,
+    /// nor evict the cached rows with `None`s.
+    ///
+    /// The filter is built by `RowCache::rebuild_filter`, which scans the keys of the rows
+    /// matching the filters of the query, and is meant to be rebuilt periodically with
+    /// `RowCache::watch_filter`, which sizes it for twice the number of scanned keys. Until
+    /// then, lookups are not filtered. The keys of the rows loaded, inserted with
+    /// `RowCache::insert` or reported as changed, e.g. by [`RowCacheBuilder::invalidation_bus`],
+    /// are added to the filter as they come, while changes of unknown rows, e.g. new
+    /// generations, disable the filter until it is rebuilt. Rows inserted into the table
+    /// otherwise are reported as absent until the next rebuild.
+    ///
+    /// Filtered lookups are counted apart from the hits and misses, see `CacheStats::filtered`.
+    /// The keys are scanned as `K`, so the filter cannot be used with keys mapped with
+    /// [`RowCacheBuilder::map_key`]: `try_build` fails with [`BuildError::MappedKeyFilter`],
+    /// and `build` panics.
+    ///
+    /// This has no effect on builders created with [`RowCacheBuilder::for_query`].
+    ///
+    /// # Arguments
+    /// * `false_positive_rate` - The ratio of absent keys that are not filtered, e.g. `0.01`.
+    pub fn existence_filter(self, false_positive_rate: f64) -> Self {
+        let mut builder = self;
+        builder.existence_filter = Some(false_positive_rate);
+        builder
+    }
+
+    /// Limits the number of database loads of the cache running at once, so that a burst of
+    /// misses cannot take all the connections of the pool from the rest of the application.
+    ///
+    /// Loads beyond the limit wait in a queue holding up to `n` loads by default, see
+    /// [`RowCacheBuilder::max_queued_loads`]. When the queue is full, lookups fail right away
+    /// with [`LoadError::Overloaded`](crate::future::LoadError::Overloaded), unless they have
+    /// a higher [priority](crate::future::LoadPriority) than a waiting load, which is shed
+    /// instead.
+    /// Concurrent lookups of a key share a single load, made with the priority of the first
+    /// lookup. Failed lookups are not cached.
+    ///
+    /// # Arguments
+    /// * `n` - The maximum number of concurrent loads, at least 1.
+    pub fn max_concurrent_loads(self, n: usize) -> Self {
+        let mut builder = self;
+        builder.max_loads = Some(n);
+        builder
+    }
+
+    /// Sets the number of loads waiting for one of the slots set with
+    /// [`RowCacheBuilder::max_concurrent_loads`], defaulting to the number of slots.
+    ///
+    /// With `0`, the loads beyond the limit fail right away.
+    ///
+    /// # Arguments
+    /// * `n` - The maximum number of waiting loads.
+    pub fn max_queued_loads(self, n: usize) -> Self {
+        let mut builder = self;
+        builder.max_queued_loads = Some(n);
         builder
     }
 
     /// Builds the `RowCache` instance.
     ///
     /// This finalizes the configuration and constructs the `RowCache` ready for use.
-    pub fn build(self) -> RowCache<DB, K, V, W> {
-        RowCache {
-            pool: self.pool,
-            query: self.query,
-            cache: self.inner.build(),
-            _0: PhantomData,
-        }
+    ///
+    /// # Panics
+    /// Panics if an existence filter is set on a builder whose keys are mapped with
+    /// [`RowCacheBuilder::map_key`], for which `try_build` fails with
+    /// [`BuildError::MappedKeyFilter`] instead.
+    pub fn build(self) -> RowCache<DB, K, V, W>
+    where
+        B: Type<DB> + for<'q> Encode<'q, DB> + SSS,
+    {
+        self.finish(CacheBuilder::build)
     }
 
     /// Builds the `RowCache` instance with a custom hash builder.
@@ -194,19 +1045,311 @@ where
     /// * `hasher` - The custom hash builder to use.
     ///
     /// See [`moka::future::CacheBuilder::build_with_hasher`] for more details.
+    ///
+    /// # Panics
+    /// Panics like [`RowCacheBuilder::build`] if an existence filter is set on a builder whose
+    /// keys are mapped.
     pub fn build_with_hasher<S>(self, hasher: S) -> RowCache<DB, K, V, W, S>
     where
         S: BuildHasher + Clone + SSS,
+        B: Type<DB> + for<'q> Encode<'q, DB> + SSS,
     {
+        self.finish(|inner| inner.build_with_hasher(hasher))
+    }
+
+    /// Builds the `RowCache` instance from the underlying cache built by `build`, and
+    /// registers it in its `CacheRegistry`, if any, sharing the budget if it was set with
+    /// [`RowCacheBuilder::share_budget`].
+    fn finish<S>(
+        self,
+        build: impl FnOnce(CacheBuilder<K, Option<W>, Cache<K, Option<W>>>) -> Cache<K, Option<W>, S>,
+    ) -> RowCache<DB, K, V, W, S>
+    where
+        S: BuildHasher + Clone + SSS,
+        B: Type<DB> + for<'q> Encode<'q, DB> + SSS,
+    {
+        if self.existence_filter.is_some() && self.table.is_some() && self.mapped_key {
+            panic!("{}", BuildError::MappedKeyFilter);
+        }
+        let mut inner = self.inner;
+        let priority = self.registry.as_ref().and_then(|(_, priority)| *priority);
+        let share = priority.map(|priority| (priority, Arc::<Share>::default()));
+        let weigher = self.weigher.map(Arc::<WeigherFn<K, W>>::from);
+        match (&self.registry, &share, &weigher) {
+            (Some((registry, _)), Some((_, share)), weigher) => {
+                let budget = registry.budget();
+                let share = Arc::clone(share);
+                let weigher = weigher.clone();
+                inner = inner.max_capacity(budget).weigher(move |key, value| {
+                    let weight = weigher.as_ref().map_or(1, |weigher| weigher(key, value));
+                    share.add(weight);
+                    registry::scale(weight, budget, share.capacity())
+                });
+            }
+            (_, _, Some(weigher)) => {
+                let weigher = Arc::clone(weigher);
+                inner = inner.weigher(move |key, value| weigher(key, value));
+            }
+            _ => {}
+        }
+        let probe = Arc::default();
+        let (expiry, early_refresh) = self.expiry_options.wrap(self.expiry);
+        inner = inner.expire_after(Probed {
+            expiry,
+            probe: Arc::clone(&probe),
+        });
+        let events = Arc::new(Events::new(self.event_capacity));
+        let dependents = Arc::<Dependents<K>>::default();
+        let listener = self.listener;
+        let publisher = Arc::clone(&events);
+        let cascade = Arc::clone(&dependents);
+        let tags = self.tags.map(|tags| Arc::new(Tags::new(tags)));
+        let index = tags.clone();
+        let usage = share.as_ref().map(|(_, share)| Arc::clone(share));
+        let drawn = early_refresh.clone();
+        inner = inner.async_eviction_listener(move |key, value, cause| {
+            if let Some(index) = &index {
+                index.remove(&key, &value);
+            }
+            if let Some(drawn) = &drawn {
+                drawn.forget(&key);
+            }
+            if let Some(usage) = &usage {
+                usage.remove(weigher.as_ref().map_or(1, |weigher| weigher(&key, &value)));
+            }
+            publisher.removed(Arc::clone(&key), &value, cause);
+            let cascade = (cause == RemovalCause::Explicit).then(|| cascade.invalidate(&key));
+            let listener = listener
+                .as_ref()
+                .map(|listener| listener(key, value, cause));
+            Box::pin(async move {
+                if let Some(cascade) = cascade {
+                    cascade.await;
+                }
+                if let Some(listener) = listener {
+                    listener.await;
+                }
+            })
+        });
+        let filter = self.existence_filter.zip(self.table.as_ref());
+        let filter = filter.map(|(rate, table)| ExistenceFilter::new(table.scan(), rate));
+        let limiter = self.max_loads.map(|max| {
+            let queue = self.max_queued_loads.unwrap_or(max);
+            LoadLimiter::new(max, queue)
+        });
+        let log = self.invalidation_log.zip(self.table).map(|(log, table)| {
+            LogTail::new(log, table.dialect, &table.name, self.parse_key.clone())
+        });
+        if let Some(link) = &self.bus {
+            let changes = Arc::downgrade(&self.changes);
+            let parse_key = self.parse_key.clone();
+            link.bus.subscribe(&link.name, move |key| {
+                let Some(changes) = changes.upgrade() else {
+                    return false;
+                };
+                let key = parse_key
+                    .as_ref()
+                    .zip(key)
+                    .and_then(|(parse, key)| parse(key));
+                changes.push(key.map_or(Change::All, Change::Key));
+                true
+            });
+        }
+        let cache = build(inner);
+        let counters = Arc::<Counters>::default();
+        let registration = self.registry.map(|(registry, _)| {
+            let inspector = Inspector {
+                cache: cache.clone(),
+                counters: Arc::clone(&counters),
+                probe: Arc::clone(&probe),
+                parse_key: self.parse_key,
+            };
+            registry.register(share, Arc::new(inspector))
+        });
         RowCache {
             pool: self.pool,
             query: self.query,
-            cache: self.inner.build_with_hasher(hasher),
+            binds: self.binds,
+            bind_key: bind_key(self.map_key),
+            map_row: self.map_row,
+            cache,
+            counters,
+            probe,
+            events,
+            dependents,
+            tags,
+            generation: Generation::new(self.generation_source),
+            changes: self.changes,
+            log,
+            bus: self.bus,
+            advisory_lock: self.advisory_lock,
+            early_refresh,
+            filter,
+            limiter,
+            _registration: registration,
             _0: PhantomData,
         }
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl<K, V, W, B> RowCacheBuilder<sqlx::Sqlite, K, V, W, B>
+where
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Invalidates the cached entries of the rows inserted, updated or deleted in the table
+    /// through the connections an `UpdateHook` is attached to.
+    ///
+    /// SQLite reports the rowids of the changed rows, which `key` maps to the keys of the
+    /// cache. For tables whose key column is an `INTEGER PRIMARY KEY`, an alias of the rowid,
+    /// this is simply `Some`. When `key` returns `None`, e.g. `|_| None` for tables keyed by
+    /// another column, the whole cache is invalidated.
+    ///
+    /// This has no effect on builders created with [`RowCacheBuilder::for_query`].
+    ///
+    /// # Arguments
+    /// * `hook` - The hook attached to the connections of the pool.
+    /// * `key` - Maps the rowid of a changed row to its key.
+    pub fn invalidate_on_update(
+        self,
+        hook: &UpdateHook,
+        key: impl Fn(i64) -> Option<K> + Send + Sync + 'static,
+    ) -> Self {
+        let Some(table) = &self.table else {
+            return self;
+        };
+        let (schema, name) = split_table(&table.name);
+        let (schema, name): (Option<Box<str>>, Box<str>) = (schema.map(Into::into), name.into());
+        let changes = Arc::downgrade(&self.changes);
+        hook.subscribe(move |database, table, rowid| {
+            let Some(changes) = changes.upgrade() else {
+                return false;
+            };
+            let database = schema.as_deref().is_none_or(|schema| schema == database);
+            if database && table.eq_ignore_ascii_case(&name) {
+                changes.push(key(rowid).map_or(Change::All, Change::Key));
+            }
+            true
+        });
+        self
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl<K, V, W, B> RowCacheBuilder<sqlx::Postgres, K, V, W, B>
+where
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Takes a transaction-level advisory lock (`pg_advisory_xact_lock`) keyed by the name of
+    /// the cache and the key before querying a key, so that the replicas of the cache missing
+    /// the same key query it one at a time rather than all at once, e.g. on a cold start.
+    ///
+    /// The loads are serialized rather than deduplicated: the replicas do not share the rows
+    /// they load, so there is nothing to re-check once the lock is held, and each replica
+    /// still queries the key, which spreads the queries over time without saving any.
+    /// Deduplicating the loads across replicas is out of scope. This is meant for expensive
+    /// queries, such as the ones given to [`RowCacheBuilder::for_query`], as every load costs
+    /// extra round trips.
+    ///
+    /// The replicas must share the name of the cache to agree on the locks, whose IDs are
+    /// hashed from the name and the key independently of the platform and the toolchain, as
+    /// long as the `Hash` implementation of the keys is, like for integers and strings. While
+    /// another replica holds the lock, the load tries again every 25 milliseconds
+    /// (`pg_try_advisory_xact_lock`) without holding a connection in between, and queries the
+    /// key without the lock once `timeout` has elapsed. A load cancelled while holding the
+    /// lock closes its connection, which releases the lock.
+    ///
+    /// # Arguments
+    /// * `timeout` - How long to wait for the lock before loading without it.
+    pub fn advisory_lock(self, timeout: Duration) -> Self {
+        let mut builder = self;
+        builder.advisory_lock = Some(advisory::postgres(timeout));
+        builder
+    }
+}
+
+#[cfg(feature = "mysql")]
+impl<K, V, W, B> RowCacheBuilder<sqlx::MySql, K, V, W, B>
+where
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Takes a named lock (`GET_LOCK`) keyed by the name of the cache and the key before
+    /// querying a key, so that the replicas of the cache missing the same key query it one at
+    /// a time rather than all at once, e.g. on a cold start.
+    ///
+    /// See the PostgreSQL variant of this method for details. The lock is tried with
+    /// `GET_LOCK(name, 0)`, which does not wait for it.
+    ///
+    /// # Arguments
+    /// * `timeout` - How long to wait for the lock before loading without it.
+    pub fn advisory_lock(self, timeout: Duration) -> Self {
+        let mut builder = self;
+        builder.advisory_lock = Some(advisory::mysql(timeout));
+        builder
+    }
+}
+
+/// Erases the type of the values bound to the query for the keys.
+fn bind_key<DB, K, B>(map_key: MapKey<K, B>) -> BindKey<DB, K>
+where
+    DB: Database,
+    K: SSS,
+    B: Type<DB> + for<'q> Encode<'q, DB> + SSS,
+{
+    Box::new(move |key, args| args.add(map_key(key)))
+}
+
+impl<DB, K, V, W, B> RowCacheBuilder<DB, K, V, W, B>
+where
+    DB: QueryBuilder + Database,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+    String: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+    Option<String>: Type<DB> + for<'q> Encode<'q, DB>,
+    usize: ColumnIndex<DB::Row>,
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+    B: Type<DB> + for<'q> Encode<'q, DB> + SSS,
+{
+    /// Validates the query against the database and builds the `RowCache` instance.
+    ///
+    /// For builders created with [`RowCacheBuilder::for_table`], the table, the key column and
+    /// the selected columns are looked up with [`table_columns`]. The query is then prepared
+    /// by the database, so that errors surface here instead of on the first lookup.
+    ///
+    /// Fails with [`BuildError::MappedKeyFilter`] if an existence filter is set on a builder
+    /// whose keys are mapped with [`RowCacheBuilder::map_key`].
+    pub async fn try_build(self) -> Result<RowCache<DB, K, V, W>, BuildError> {
+        if self.existence_filter.is_some() && self.table.is_some() && self.mapped_key {
+            return Err(BuildError::MappedKeyFilter);
+        }
+        if let Some(table) = &self.table {
+            let columns = table_columns(&self.pool, &table.name).await?;
+            if columns.is_empty() {
+                return Err(BuildError::TableNotFound(table.name.clone()));
+            }
+            let selected = table.columns.iter().flatten();
+            for column in std::iter::once(&table.id).chain(selected) {
+                if !columns.iter().any(|c| c.as_str() == column.as_ref()) {
+                    return Err(BuildError::ColumnNotFound {
+                        table: table.name.clone(),
+                        column: column.clone(),
+                    });
+                }
+            }
+        }
+        self.pool.prepare(&self.query).await?;
+        Ok(self.build())
+    }
+}
+
 /// Implements the wrapper methods
 macro_rules! impl_wrapper {
     (
@@ -218,12 +1361,12 @@ macro_rules! impl_wrapper {
             ;
         )*
     ) => {
-        impl<DB, K, V, W> RowCacheBuilder<DB, K, V, W>
+        impl<DB, K, V, W, B> RowCacheBuilder<DB, K, V, W, B>
         where
             DB: Database,
             K: Clone + Hash + Eq + SSS,
             V: Unpin + SSS,
-            W: From<V> + Clone + SSS,
+            W: Clone + SSS,
         {
             $(
                 #[doc = concat!(
@@ -249,19 +1392,6 @@ impl_wrapper! {
     pub fn max_capacity(self, max_capacity: u64) -> Self;
     pub fn initial_capacity(self, number_of_entries: usize) -> Self;
     pub fn eviction_policy(self, policy: EvictionPolicy) -> Self;
-    pub fn weigher(
-        self,
-        weigher: impl Fn(&K, &Option<W>) -> u32 + Send + Sync + 'static
-    ) -> Self;
-    pub fn eviction_listener(
-        self,
-        listener: impl Fn(Arc<K>, Option<W>, RemovalCause) + Send + Sync + 'static
-    ) -> Self;
-    pub fn async_eviction_listener(
-        self,
-        listener: impl Fn(Arc<K>, Option<W>, RemovalCause) -> ListenerFuture + Send + Sync + 'static
-    ) -> Self;
-    pub fn expire_after(self, expiry: impl Expiry<K, Option<W>> + SSS) -> Self;
     pub fn support_invalidation_closures(self) -> Self;
 }
 
@@ -297,4 +1427,15 @@ impl<K, W> Expiry<K, Option<W>> for DefaultExpiry {
             None => Some(self.ttl_for_none),
         }
     }
+
+    /// Restarts the expiry of a replaced value, like the time-to-live of `moka`.
+    fn expire_after_update(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.expire_after_create(key, value, updated_at)
+    }
 }
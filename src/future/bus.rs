@@ -0,0 +1,641 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasher, Hasher, RandomState},
+    io,
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
+
+use tokio::sync::{broadcast, mpsc};
+
+use crate::future::registry::BoxFuture;
+
+#[cfg(feature = "redis")]
+pub use pubsub::RedisTransport;
+pub use tcp::TcpTransport;
+
+/// The delay before receiving again after a transport failed to receive.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The number of messages waiting to be sent, beyond which the caches of the messages are
+/// invalidated as a whole.
+const OUTBOX_CAPACITY: usize = 1024;
+
+/// Carries the messages of an [`InvalidationBus`] between the replicas of an application.
+///
+/// Messages are opaque bytes, to be delivered to the buses of the other replicas, and
+/// possibly back to the bus sending them. Delivery is best-effort: a transport reports the
+/// messages it may have missed, e.g. after reconnecting, so that the caches of the bus are
+/// invalidated as a whole.
+pub trait BusTransport: Send + Sync + 'static {
+    /// Sends a message to the other replicas.
+    fn send<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Receives the next message from the other replicas.
+    ///
+    /// Returns `Ok(None)` when messages may have been missed, e.g. when the transport
+    /// (re)connected or lagged behind.
+    fn recv(&self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>>;
+}
+
+/// Broadcasts the invalidations of `RowCache`s to their replicas in other processes.
+///
+/// Caches join a bus with
+/// [`RowCacheBuilder::invalidation_bus`](crate::future::RowCacheBuilder::invalidation_bus),
+/// under a name shared by their replicas. The keys inserted with `RowCache::insert`, reloaded
+/// with `RowCache::refresh` or invalidated with `RowCache::broadcast_invalidate` are sent
+/// over the transport of the bus, and invalidated by the replicas on their next lookup.
+///
+/// Messages are queued without waiting for the transport, then sent and received by
+/// [`InvalidationBus::run`], which must be spawned on the runtime. Each bus has a random
+/// origin, so that it ignores the messages it sent itself. Whenever messages may have been
+/// missed, all the caches of the bus are invalidated.
+#[derive(Clone)]
+pub struct InvalidationBus {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    origin: u64,
+    transport: Box<dyn BusTransport>,
+    subscribers: Mutex<HashMap<Box<str>, Vec<Subscriber>>>,
+    /// The messages waiting to be sent by `InvalidationBus::run`.
+    outbox: mpsc::Sender<Vec<u8>>,
+    queued: tokio::sync::Mutex<mpsc::Receiver<Vec<u8>>>,
+    /// The caches whose messages did not fit in the outbox, to be invalidated as a whole.
+    overflowed: Mutex<HashSet<Box<str>>>,
+}
+
+/// Invalidates a key of a cache (or the whole cache for `None`). Returns `false` once the
+/// cache is dropped.
+type Subscriber = Box<dyn Fn(Option<&str>) -> bool + Send + Sync>;
+
+impl InvalidationBus {
+    /// Creates a bus sending its messages over a transport.
+    pub fn new(transport: impl BusTransport) -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        let (outbox, queued) = mpsc::channel(OUTBOX_CAPACITY);
+        InvalidationBus {
+            inner: Arc::new(Inner {
+                origin: hasher.finish(),
+                transport: Box::new(transport),
+                subscribers: Mutex::default(),
+                outbox,
+                queued: tokio::sync::Mutex::new(queued),
+                overflowed: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Queues the invalidation of a key of the caches named `cache` for the other replicas,
+    /// or of all their keys for `None`, to be sent by [`InvalidationBus::run`].
+    ///
+    /// When too many messages are waiting to be sent, the invalidation of all the keys of the
+    /// caches is sent instead. Fails only if the name of the caches is too long.
+    ///
+    /// # Arguments
+    /// * `cache` - The name the caches joined the bus with.
+    /// * `key` - The key to invalidate, as parsed by the key parser of the caches.
+    pub fn publish(&self, cache: &str, key: Option<&str>) -> io::Result<()> {
+        let message = encode(self.inner.origin, cache, key)?;
+        // the outbox is only full if messages are queued, so the overflow is seen by the
+        // sender once it sent them
+        let mut overflowed = self.inner.overflowed.lock().unwrap();
+        if self.inner.outbox.try_send(message).is_err() {
+            overflowed.insert(cache.into());
+        }
+        Ok(())
+    }
+
+    /// Sends the queued messages to the other replicas, receives their messages and
+    /// invalidates the entries of the caches of the bus.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime. When
+    /// the transport fails to receive, all the caches are invalidated and the transport is
+    /// polled again after a second. Failures are reported as warnings with the `tracing`
+    /// feature.
+    pub async fn run(&self) {
+        let mut send = pin!(self.send());
+        let mut receive = pin!(self.receive());
+        std::future::poll_fn(|cx| {
+            let _ = send.as_mut().poll(cx);
+            let _ = receive.as_mut().poll(cx);
+            Poll::<()>::Pending
+        })
+        .await
+    }
+
+    /// Sends the queued messages, one at a time, followed by the invalidations of the caches
+    /// whose messages overflowed the outbox.
+    async fn send(&self) {
+        let mut queued = self.inner.queued.lock().await;
+        loop {
+            let overflowed = std::mem::take(&mut *self.inner.overflowed.lock().unwrap());
+            for cache in overflowed {
+                if let Ok(message) = encode(self.inner.origin, &cache, None) {
+                    self.transmit(&message).await;
+                }
+            }
+            // the bus holds a sender, so the outbox cannot be closed
+            let Some(message) = queued.recv().await else {
+                return;
+            };
+            self.transmit(&message).await;
+        }
+    }
+
+    async fn transmit(&self, message: &[u8]) {
+        let _result = self.inner.transport.send(message).await;
+        #[cfg(feature = "tracing")]
+        if let Err(error) = _result {
+            tracing::warn!(%error, "failed to send an invalidation");
+        }
+    }
+
+    async fn receive(&self) {
+        loop {
+            match self.inner.transport.recv().await {
+                Ok(Some(message)) => match decode(&message) {
+                    Some((origin, _, _)) if origin == self.inner.origin => {}
+                    Some((_, cache, key)) => self.deliver(Some(cache), key),
+                    None => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("ignored a malformed invalidation message");
+                    }
+                },
+                Ok(None) => self.deliver(None, None),
+                Err(_error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_error, "failed to receive invalidations");
+                    self.deliver(None, None);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Invalidates a key of the caches named `cache`, or all the caches for `None`.
+    fn deliver(&self, cache: Option<&str>, key: Option<&str>) {
+        let mut subscribers = self.inner.subscribers.lock().unwrap();
+        subscribers.retain(|name, subscribers| {
+            if cache.is_none_or(|cache| cache == name.as_ref()) {
+                subscribers.retain(|subscriber| subscriber(key));
+            }
+            !subscribers.is_empty()
+        });
+    }
+
+    pub(crate) fn subscribe(
+        &self,
+        cache: &str,
+        subscriber: impl Fn(Option<&str>) -> bool + Send + Sync + 'static,
+    ) {
+        let mut subscribers = self.inner.subscribers.lock().unwrap();
+        let subscribers = subscribers.entry(cache.into()).or_default();
+        subscribers.push(Box::new(subscriber));
+    }
+}
+
+/// The bus a `RowCache` joined, along with its name and the formatter of its keys.
+pub(crate) struct BusLink<K> {
+    pub(crate) bus: InvalidationBus,
+    pub(crate) name: Box<str>,
+    pub(crate) format_key: Box<dyn Fn(&K) -> String + Send + Sync>,
+}
+
+/// Encodes a message as its origin, the length of the name of the cache and the name, followed
+/// by the key if any.
+fn encode(origin: u64, cache: &str, key: Option<&str>) -> io::Result<Vec<u8>> {
+    let len = u16::try_from(cache.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "cache name too long"))?;
+    let mut message = Vec::with_capacity(12 + cache.len() + key.map_or(0, str::len));
+    message.extend(origin.to_be_bytes());
+    message.extend(len.to_be_bytes());
+    message.extend(cache.as_bytes());
+    match key {
+        Some(key) => {
+            message.push(1);
+            message.extend(key.as_bytes());
+        }
+        None => message.push(0),
+    }
+    Ok(message)
+}
+
+fn decode(message: &[u8]) -> Option<(u64, &str, Option<&str>)> {
+    let (origin, rest) = message.split_first_chunk::<8>()?;
+    let (len, rest) = rest.split_first_chunk::<2>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+    let (cache, rest) = rest.split_at_checked(len)?;
+    let cache = std::str::from_utf8(cache).ok()?;
+    let key = match rest.split_first()? {
+        (0, []) => None,
+        (1, key) => Some(std::str::from_utf8(key).ok()?),
+        _ => return None,
+    };
+    Some((u64::from_be_bytes(*origin), cache, key))
+}
+
+/// A transport delivering the messages of an [`InvalidationBus`] within a process, e.g. to
+/// test the replicas of an application.
+///
+/// Clones of the transport share the same channel, and receive all the messages sent on it,
+/// including their own.
+pub struct LocalTransport {
+    sender: broadcast::Sender<Arc<[u8]>>,
+    receiver: tokio::sync::Mutex<broadcast::Receiver<Arc<[u8]>>>,
+}
+
+impl LocalTransport {
+    /// Creates a channel holding up to `capacity` messages not yet received by every clone.
+    ///
+    /// Clones lagging further behind miss messages, which invalidates the caches of their bus.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = broadcast::channel(capacity);
+        LocalTransport {
+            sender,
+            receiver: tokio::sync::Mutex::new(receiver),
+        }
+    }
+}
+
+impl Clone for LocalTransport {
+    fn clone(&self) -> Self {
+        LocalTransport {
+            sender: self.sender.clone(),
+            receiver: tokio::sync::Mutex::new(self.sender.subscribe()),
+        }
+    }
+}
+
+impl BusTransport for LocalTransport {
+    fn send<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        // the transport holds a receiver, so the channel cannot be closed
+        let _ = self.sender.send(message.into());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn recv(&self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async {
+            match self.receiver.lock().await.recv().await {
+                Ok(message) => Ok(Some(message.to_vec())),
+                Err(broadcast::error::RecvError::Lagged(_)) => Ok(None),
+                Err(broadcast::error::RecvError::Closed) => Err(io::ErrorKind::BrokenPipe.into()),
+            }
+        })
+    }
+}
+
+mod tcp {
+    use std::{
+        io,
+        net::SocketAddr,
+        pin::Pin,
+        task::{Poll, ready},
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncRead, AsyncWriteExt, ReadBuf},
+        net::{TcpListener, TcpStream},
+        sync::Mutex,
+        time::Instant,
+    };
+
+    use crate::future::{bus::BusTransport, registry::BoxFuture};
+
+    /// The maximum length of a message, beyond which the connection is closed.
+    const MAX_MESSAGE: usize = 64 * 1024;
+
+    /// A transport sending the messages of an [`InvalidationBus`](super::InvalidationBus)
+    /// to a fixed set of peers over TCP.
+    ///
+    /// Every replica listens for the connections of its peers, and connects to each of its
+    /// peers to send its messages, each prefixed with its length. Messages are sent to the
+    /// peers concurrently. Connections are opened on the first message and reopened after a
+    /// failure, skipping unreachable peers for the connect timeout.
+    ///
+    /// A replica that failed to send messages to a peer tells it with an empty message once
+    /// it reconnects, so that the caches of the bus of the peer are invalidated, as they are
+    /// when an inbound connection fails. Connecting alone does not invalidate the caches.
+    pub struct TcpTransport {
+        inbound: Mutex<Inbound>,
+        local_addr: SocketAddr,
+        peers: Vec<Peer>,
+        connect_timeout: Duration,
+    }
+
+    struct Inbound {
+        listener: TcpListener,
+        connections: Vec<Connection>,
+    }
+
+    struct Connection {
+        stream: TcpStream,
+        buffer: Vec<u8>,
+    }
+
+    struct Peer {
+        addr: SocketAddr,
+        state: Mutex<PeerState>,
+    }
+
+    #[derive(Default)]
+    struct PeerState {
+        stream: Option<TcpStream>,
+        /// When to try to reconnect to the peer after failing to connect.
+        retry_at: Option<Instant>,
+        /// Whether messages may not have reached the peer since it was last told so.
+        missed: bool,
+    }
+
+    impl TcpTransport {
+        /// Listens for the connections of the peers on an address.
+        pub async fn bind(addr: impl tokio::net::ToSocketAddrs) -> io::Result<Self> {
+            let listener = TcpListener::bind(addr).await?;
+            Ok(TcpTransport {
+                local_addr: listener.local_addr()?,
+                inbound: Mutex::new(Inbound {
+                    listener,
+                    connections: Vec::new(),
+                }),
+                peers: Vec::new(),
+                connect_timeout: Duration::from_secs(1),
+            })
+        }
+
+        /// Adds peers to send the messages to.
+        pub fn peers(self, peers: impl IntoIterator<Item = SocketAddr>) -> Self {
+            let mut transport = self;
+            transport.peers.extend(peers.into_iter().map(|addr| Peer {
+                addr,
+                state: Mutex::default(),
+            }));
+            transport
+        }
+
+        /// Sets the timeout of the connections to the peers. Defaults to 1 second.
+        pub fn connect_timeout(self, timeout: Duration) -> Self {
+            let mut transport = self;
+            transport.connect_timeout = timeout;
+            transport
+        }
+
+        /// Returns the address the transport listens on, e.g. to find the port assigned when
+        /// binding to port 0.
+        pub fn local_addr(&self) -> SocketAddr {
+            self.local_addr
+        }
+
+        async fn send_to(&self, peer: &Peer, message: &[u8]) -> io::Result<()> {
+            let mut state = peer.state.lock().await;
+            if state.retry_at.is_some_and(|at| at > Instant::now()) {
+                state.missed = true;
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            // a connection that failed is reopened once, as the peer may have restarted
+            let mut reconnect = state.stream.is_none();
+            loop {
+                if state.stream.is_none() {
+                    let connect = TcpStream::connect(peer.addr);
+                    let stream = match tokio::time::timeout(self.connect_timeout, connect).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => return Err(state.failed(self.connect_timeout, e)),
+                        Err(_) => {
+                            let e = io::ErrorKind::TimedOut.into();
+                            return Err(state.failed(self.connect_timeout, e));
+                        }
+                    };
+                    stream.set_nodelay(true)?;
+                    state.stream = Some(stream);
+                }
+                // an empty message tells the peer that it may have missed messages
+                let mut frame = Vec::with_capacity(8 + message.len());
+                if state.missed {
+                    frame.extend(0_u32.to_be_bytes());
+                }
+                frame.extend((message.len() as u32).to_be_bytes());
+                frame.extend(message);
+                let stream = state.stream.as_mut().unwrap();
+                match stream.write_all(&frame).await {
+                    Ok(()) => {
+                        state.missed = false;
+                        return Ok(());
+                    }
+                    Err(e) if reconnect => return Err(state.failed(Duration::ZERO, e)),
+                    // the messages written before the failure may have been lost
+                    Err(_) => {
+                        state.stream = None;
+                        state.missed = true;
+                        reconnect = true;
+                    }
+                }
+            }
+        }
+    }
+
+    impl PeerState {
+        fn failed(&mut self, backoff: Duration, error: io::Error) -> io::Error {
+            self.stream = None;
+            self.retry_at = Some(Instant::now() + backoff);
+            self.missed = true;
+            error
+        }
+    }
+
+    impl BusTransport for TcpTransport {
+        fn send<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                if message.len() > MAX_MESSAGE {
+                    return Err(io::ErrorKind::InvalidInput.into());
+                }
+                let mut sends: Vec<_> = self
+                    .peers
+                    .iter()
+                    .map(|peer| Box::pin(self.send_to(peer, message)))
+                    .collect();
+                let mut result = Ok(());
+                std::future::poll_fn(|cx| {
+                    sends.retain_mut(|send| match send.as_mut().poll(cx) {
+                        Poll::Ready(sent) => {
+                            if let Err(e) = sent {
+                                result = Err(e);
+                            }
+                            false
+                        }
+                        Poll::Pending => true,
+                    });
+                    match sends.is_empty() {
+                        true => Poll::Ready(()),
+                        false => Poll::Pending,
+                    }
+                })
+                .await;
+                result
+            })
+        }
+
+        fn recv(&self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+            Box::pin(async {
+                let mut inbound = self.inbound.lock().await;
+                std::future::poll_fn(|cx| inbound.poll_recv(cx)).await
+            })
+        }
+    }
+
+    impl Inbound {
+        fn poll_recv(
+            &mut self,
+            cx: &mut std::task::Context<'_>,
+        ) -> Poll<io::Result<Option<Vec<u8>>>> {
+            while let Poll::Ready(accepted) = self.listener.poll_accept(cx) {
+                let (stream, _) = accepted?;
+                self.connections.push(Connection {
+                    stream,
+                    buffer: Vec::new(),
+                });
+            }
+            let mut i = 0;
+            while i < self.connections.len() {
+                match self.connections[i].poll_message(cx) {
+                    Poll::Ready(Ok(Some(message))) if message.is_empty() => {
+                        return Poll::Ready(Ok(None));
+                    }
+                    Poll::Ready(Ok(Some(message))) => return Poll::Ready(Ok(Some(message))),
+                    // the peer closed the connection between messages, and reconnects on its
+                    // next message
+                    Poll::Ready(Ok(None)) => drop(self.connections.swap_remove(i)),
+                    // a message may have been lost with the connection
+                    Poll::Ready(Err(_)) => {
+                        drop(self.connections.swap_remove(i));
+                        return Poll::Ready(Ok(None));
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            Poll::Pending
+        }
+    }
+
+    impl Connection {
+        /// Reads the next message, or `None` if the peer closed the connection between
+        /// messages.
+        fn poll_message(
+            &mut self,
+            cx: &mut std::task::Context<'_>,
+        ) -> Poll<io::Result<Option<Vec<u8>>>> {
+            loop {
+                if let Some((len, rest)) = self.buffer.split_first_chunk::<4>() {
+                    let len = u32::from_be_bytes(*len) as usize;
+                    if len > MAX_MESSAGE {
+                        return Poll::Ready(Err(io::ErrorKind::InvalidData.into()));
+                    }
+                    if rest.len() >= len {
+                        let message = rest[..len].to_vec();
+                        self.buffer.drain(..4 + len);
+                        return Poll::Ready(Ok(Some(message)));
+                    }
+                }
+                let mut chunk = [0; 4096];
+                let mut read = ReadBuf::new(&mut chunk);
+                ready!(Pin::new(&mut self.stream).poll_read(cx, &mut read))?;
+                if read.filled().is_empty() && self.buffer.is_empty() {
+                    return Poll::Ready(Ok(None));
+                }
+                if read.filled().is_empty() {
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                self.buffer.extend_from_slice(read.filled());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+mod pubsub {
+    use std::io;
+
+    use futures_util::StreamExt;
+    use redis::{
+        Client,
+        aio::{MultiplexedConnection, PubSubStream},
+    };
+    use tokio::sync::Mutex;
+
+    use crate::future::{bus::BusTransport, registry::BoxFuture};
+
+    /// A transport sending the messages of an [`InvalidationBus`](super::InvalidationBus)
+    /// over a Redis pub/sub channel.
+    ///
+    /// The connections are opened on first use and reopened after a failure. The caches of
+    /// the bus are invalidated whenever the transport subscribes to the channel, as messages
+    /// may have been published while it was not subscribed.
+    pub struct RedisTransport {
+        client: Client,
+        channel: Box<str>,
+        publisher: Mutex<Option<MultiplexedConnection>>,
+        subscriber: Mutex<Option<PubSubStream>>,
+    }
+
+    impl RedisTransport {
+        /// Creates a transport publishing to a channel of a Redis server.
+        ///
+        /// # Arguments
+        /// * `client` - The client of the server.
+        /// * `channel` - The channel shared by the replicas.
+        pub fn new(client: Client, channel: &str) -> Self {
+            RedisTransport {
+                client,
+                channel: channel.into(),
+                publisher: Mutex::default(),
+                subscriber: Mutex::default(),
+            }
+        }
+    }
+
+    impl BusTransport for RedisTransport {
+        fn send<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                let mut publisher = self.publisher.lock().await;
+                let connection = match &mut *publisher {
+                    Some(connection) => connection,
+                    None => {
+                        let connection = self.client.get_multiplexed_async_connection().await;
+                        publisher.insert(connection.map_err(io::Error::other)?)
+                    }
+                };
+                let mut publish = redis::cmd("PUBLISH");
+                publish.arg(&*self.channel).arg(message);
+                let result = publish.query_async::<i64>(connection).await;
+                if result.is_err() {
+                    *publisher = None;
+                }
+                result.map(drop).map_err(io::Error::other)
+            })
+        }
+
+        fn recv(&self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+            Box::pin(async {
+                let mut subscriber = self.subscriber.lock().await;
+                let Some(stream) = subscriber.as_mut() else {
+                    let pubsub = self.client.get_async_pubsub().await;
+                    let mut pubsub = pubsub.map_err(io::Error::other)?;
+                    let subscribe = pubsub.subscribe(&*self.channel).await;
+                    subscribe.map_err(io::Error::other)?;
+                    *subscriber = Some(pubsub.into_on_message());
+                    return Ok(None);
+                };
+                match stream.next().await {
+                    Some(message) => Ok(Some(message.get_payload_bytes().to_vec())),
+                    None => {
+                        *subscriber = None;
+                        Err(io::ErrorKind::ConnectionAborted.into())
+                    }
+                }
+            })
+        }
+    }
+}
@@ -3,14 +3,42 @@ use std::{
     hash::{BuildHasher, Hash, RandomState},
     marker::PhantomData,
     ops::Deref,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
 use moka::future::Cache;
 use send_sync_static::SSS;
-use sqlx::{Database, Encode, Executor, FromRow, IntoArguments, Pool, Type};
+use sqlx::{
+    ColumnIndex, Connection, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool, Type,
+};
+use tokio::{sync::broadcast, time::sleep};
+
+use crate::future::{
+    advisory::{AdvisoryLock, LockingConnection, RETRY_INTERVAL},
+    bloom::ExistenceFilter,
+    builder::{Bind, BindKey, MapRow, QueryBuilder, RowCacheBuilder},
+    bus::BusLink,
+    change::{ChangeEvent, ChangeOp},
+    changes::{Change, Changes},
+    dependents::Dependents,
+    entry::{EntryState, Probe},
+    error::LoadError,
+    events::{CacheEvent, EventCause, Events, Presence},
+    expiry::EarlyRefresh,
+    generation::Generation,
+    limit::{LoadLimiter, LoadPriority},
+    log::LogTail,
+    registry::Registration,
+    stats::{CacheStats, Counters},
+    tags::Tags,
+};
 
-use crate::future::builder::{QueryBuilder, RowCacheBuilder};
+/// The number of times a load fetches its row while new generations start.
+const MAX_FETCHES: usize = 3;
 
 /// A row-based asynchronous cache that integrates with `sqlx` database pools.
 ///
@@ -35,17 +63,35 @@ use crate::future::builder::{QueryBuilder, RowCacheBuilder};
 ///           (e.g., primitive types like `i32` or `f64`), `W` can directly be `V`, avoiding
 ///           the overhead of a smart pointer.
 /// * `S`: The type of the hash builder for the underlying `moka` cache. Defaults to `RandomState`.
+#[allow(clippy::doc_overindented_list_items)]
 pub struct RowCache<DB: Database, K, V, W = Arc<V>, S = RandomState> {
     pub(crate) pool: Pool<DB>,
     pub(crate) query: Box<str>,
+    pub(crate) binds: Vec<Bind<DB>>,
+    pub(crate) bind_key: BindKey<DB, K>,
+    pub(crate) map_row: MapRow<V, W>,
     pub(crate) cache: Cache<K, Option<W>, S>,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) probe: Arc<Probe<K>>,
+    pub(crate) events: Arc<Events<K>>,
+    pub(crate) dependents: Arc<Dependents<K>>,
+    pub(crate) tags: Option<Arc<Tags<K, W>>>,
+    pub(crate) generation: Generation,
+    pub(crate) changes: Arc<Changes<K>>,
+    pub(crate) log: Option<LogTail<K>>,
+    pub(crate) bus: Option<BusLink<K>>,
+    pub(crate) advisory_lock: Option<AdvisoryLock<DB>>,
+    pub(crate) early_refresh: Option<Arc<EarlyRefresh<K>>>,
+    pub(crate) filter: Option<ExistenceFilter>,
+    pub(crate) limiter: Option<LoadLimiter>,
+    pub(crate) _registration: Option<Registration>,
     pub(crate) _0: PhantomData<(V, S)>,
 }
 
 impl<DB, K, V, W> RowCache<DB, K, V, W>
 where
     DB: Database,
-    K: Clone + Hash + Eq + SSS,
+    K: Type<DB> + for<'q> Encode<'q, DB> + Clone + Hash + Eq + SSS,
     V: Unpin + SSS,
     W: From<V> + Clone + SSS,
 {
@@ -57,7 +103,7 @@ where
     /// # Arguments
     /// * `max_capacity` - The maximum number of entries the cache can hold.
     /// * `pool` - The `sqlx` database connection pool.
-    /// * `table` - The name of the database table.
+    /// * `table` - The name of the database table, optionally qualified by its schema.
     pub fn new(max_capacity: u64, pool: Pool<DB>, table: &str) -> Self
     where
         DB: QueryBuilder,
@@ -74,7 +120,7 @@ where
     /// # Arguments
     /// * `max_capacity` - The maximum number of entries the cache can hold.
     /// * `pool` - The `sqlx` database connection pool.
-    /// * `table` - The name of the database table.
+    /// * `table` - The name of the database table, optionally qualified by its schema.
     /// * `id` - The name of the primary key column (e.g., "product_id", "uuid").
     pub fn for_table(max_capacity: u64, pool: Pool<DB>, table: &str, id: &str) -> Self
     where
@@ -106,9 +152,9 @@ where
     DB: Database,
     for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
     for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
-    K: Type<DB> + for<'q> Encode<'q, DB> + Hash + Eq + Clone + SSS,
+    K: Hash + Eq + Clone + SSS,
     V: for<'r> FromRow<'r, DB::Row> + Unpin + SSS,
-    W: From<V> + Clone + SSS,
+    W: Clone + SSS,
     S: BuildHasher + Clone + SSS,
 {
     /// Attempts to retrieve a value from the cache using its key.
@@ -119,20 +165,72 @@ where
     ///
     /// Returns `Ok(Some(W))` if the row is found and successfully retrieved/fetched.
     /// Returns `Ok(None)` if the row is not found in the database.
-    /// Returns `Err(Arc<sqlx::Error>)` if a database error occurs during fetching.
+    /// Returns `Err(Arc<LoadError>)` if a database error occurs during fetching, or if the
+    /// row cannot be converted by [`RowCacheBuilder::map_row`].
+    ///
+    /// With the `tracing` feature, the lookup is wrapped in a `row_cache.get` span recording
+    /// the name of the cache and the outcome of the lookup (`hit`, `null_hit`, `miss` or
+    /// `filtered`).
     ///
     /// # Arguments
-    /// * `key` - The key to look up in the cache and bind to the database query.
-    pub async fn try_get(&self, key: K) -> Result<Option<W>, Arc<sqlx::Error>> {
-        self.cache
-            .try_get_with(key.clone(), async move {
-                sqlx::query_as::<_, V>(self.query.borrow())
-                    .bind(key)
-                    .fetch_optional(&self.pool)
-                    .await
-                    .map(|o| o.map(W::from))
-            })
-            .await
+    /// * `key` - The key to look up in the cache and bind to the database query
+    ///   (after being mapped by [`RowCacheBuilder::map_key`], if any).
+    pub async fn try_get(&self, key: K) -> Result<Option<W>, Arc<LoadError>> {
+        self.try_get_with_priority(key, LoadPriority::Normal).await
+    }
+
+    /// Attempts to retrieve a value from the cache using its key, loading it with a given
+    /// priority if the loads of the cache are limited with
+    /// [`RowCacheBuilder::max_concurrent_loads`].
+    ///
+    /// This is otherwise the same as [`RowCache::try_get`]. When the load is shed, the lookup
+    /// fails with [`LoadError::Overloaded`].
+    ///
+    /// # Arguments
+    /// * `key` - The key to look up.
+    /// * `priority` - The priority of the load, if the key is not cached.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "row_cache.get",
+        skip_all,
+        fields(cache = self.cache.name(), outcome),
+        err,
+    ))]
+    pub async fn try_get_with_priority(
+        &self,
+        key: K,
+        priority: LoadPriority,
+    ) -> Result<Option<W>, Arc<LoadError>> {
+        self.apply_changes().await;
+        if self.filtered(&key) {
+            return Ok(None);
+        }
+        let generation = AtomicU64::default();
+        let load = async {
+            let (value, loaded) = self.load(&key, priority).await?;
+            generation.store(loaded, Ordering::Relaxed);
+            Ok::<_, LoadError>(value)
+        };
+        let entry = self
+            .cache
+            .entry(key.clone())
+            .or_try_insert_with(load)
+            .await?;
+        self.counters.record(entry.is_fresh());
+        #[cfg(feature = "tracing")]
+        record_outcome(entry.is_fresh(), entry.value().is_some());
+        if entry.is_fresh() {
+            self.inserted(
+                entry.key(),
+                entry.value(),
+                EventCause::Load,
+                Presence::Absent,
+            );
+            self.discard_stale(entry.key(), generation.into_inner())
+                .await;
+        } else {
+            self.queue_early_refresh(entry.key());
+        }
+        Ok(entry.into_value())
     }
 
     /// Attempts to retrieve a value from the cache using a reference to its key.
@@ -143,25 +241,728 @@ where
     ///
     /// Returns `Ok(Some(W))` if the row is found and successfully retrieved/fetched.
     /// Returns `Ok(None)` if the row is not found in the database.
-    /// Returns `Err(Arc<sqlx::Error>)` if a database error occurs during fetching.
+    /// Returns `Err(Arc<LoadError>)` if a database error occurs during fetching, or if the
+    /// row cannot be converted by [`RowCacheBuilder::map_row`].
+    ///
+    /// With the `tracing` feature, the lookup is wrapped in a `row_cache.get` span, like
+    /// [`RowCache::try_get`].
     ///
     /// # Arguments
     /// * `key` - A reference to the key to look up. `Q` must be hashable, equatable,
     ///           and convertible to `K`.
-    pub async fn try_get_by_ref<Q>(&self, key: &Q) -> Result<Option<W>, Arc<sqlx::Error>>
+    #[allow(clippy::doc_overindented_list_items)]
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "row_cache.get",
+        skip_all,
+        fields(cache = self.cache.name(), outcome),
+        err,
+    ))]
+    pub async fn try_get_by_ref<Q>(&self, key: &Q) -> Result<Option<W>, Arc<LoadError>>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ToOwned<Owned = K>,
     {
-        self.cache
-            .try_get_with_by_ref(key, async move {
-                sqlx::query_as::<_, V>(self.query.borrow())
-                    .bind(key.to_owned()) // Use key.to_owned() for the database query
-                    .fetch_optional(&self.pool)
-                    .await
-                    .map(|o| o.map(W::from))
+        self.apply_changes().await;
+        if self.filtered(key) {
+            return Ok(None);
+        }
+        let generation = AtomicU64::default();
+        let load = async {
+            // Use key.to_owned() for the database query
+            let (value, loaded) = self.load(&key.to_owned(), LoadPriority::Normal).await?;
+            generation.store(loaded, Ordering::Relaxed);
+            Ok::<_, LoadError>(value)
+        };
+        let entry = self
+            .cache
+            .entry_by_ref(key)
+            .or_try_insert_with(load)
+            .await?;
+        self.counters.record(entry.is_fresh());
+        #[cfg(feature = "tracing")]
+        record_outcome(entry.is_fresh(), entry.value().is_some());
+        if entry.is_fresh() {
+            self.inserted(
+                entry.key(),
+                entry.value(),
+                EventCause::Load,
+                Presence::Absent,
+            );
+            self.discard_stale(entry.key(), generation.into_inner())
+                .await;
+        } else {
+            self.queue_early_refresh(entry.key());
+        }
+        Ok(entry.into_value())
+    }
+
+    /// Returns whether a key is definitely absent according to the filter set with
+    /// [`RowCacheBuilder::existence_filter`], counting the lookup as filtered if so.
+    fn filtered<Q: Hash + ?Sized>(&self, key: &Q) -> bool {
+        let filter = self.filter.as_ref();
+        if filter.is_none_or(|filter| filter.may_contain(key)) {
+            return false;
+        }
+        self.counters.record_filtered();
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("outcome", "filtered");
+        true
+    }
+
+    /// Queues the refresh of a cached key for `run_early_refresh` if its lookup was drawn to
+    /// refresh it early, as set with [`RowCacheBuilder::early_refresh`].
+    fn queue_early_refresh(&self, key: &K) {
+        if let Some(early) = &self.early_refresh {
+            early.queue(key);
+        }
+    }
+
+    /// Refreshes the entries whose lookups were drawn to refresh them early, as set with
+    /// [`RowCacheBuilder::early_refresh`], so that the lookups return the cached values
+    /// right away.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime, like
+    /// `InvalidationBus::run`, with the cache in an `Arc`. It completes immediately if early
+    /// refreshes are not enabled. The entries are reloaded like [`RowCache::refresh`], as
+    /// [`LoadPriority::Low`] loads, but without telling the replicas of the cache. Refreshed
+    /// values equal to the cached ones only extend the expiry of their entry, without
+    /// invalidating the entries of the caches depending on them. Failed refreshes leave the
+    /// cached entries untouched.
+    pub async fn run_early_refresh(&self)
+    where
+        W: PartialEq,
+    {
+        let Some(early) = &self.early_refresh else {
+            return;
+        };
+        loop {
+            for key in early.next().await {
+                let Ok((value, generation)) = self.load(&key, LoadPriority::Low).await else {
+                    continue;
+                };
+                self.upsert_with(key.clone(), value, EventCause::Refresh, PartialEq::eq)
+                    .await;
+                self.discard_stale(&key, generation).await;
+            }
+        }
+    }
+
+    /// Reloads the row of a key from the database and replaces its cached entry, if any.
+    ///
+    /// Returns the reloaded value, or the error of the database if the row cannot be
+    /// reloaded, in which case the cached entry is left untouched.
+    ///
+    /// # Arguments
+    /// * `key` - The key to reload.
+    pub async fn refresh(&self, key: K) -> Result<Option<W>, Arc<LoadError>> {
+        let value = self.reload(key.clone(), LoadPriority::Normal).await?;
+        self.broadcast(Some(&key));
+        Ok(value)
+    }
+
+    /// Reloads the row of a key and replaces its cached entry, without telling the replicas
+    /// of the cache.
+    async fn reload(&self, key: K, priority: LoadPriority) -> Result<Option<W>, Arc<LoadError>> {
+        let (value, generation) = self.load(&key, priority).await?;
+        self.upsert(key.clone(), value.clone(), EventCause::Refresh)
+            .await;
+        self.discard_stale(&key, generation).await;
+        Ok(value)
+    }
+
+    /// Discards the newly cached entry of a key if a new generation started since its row was
+    /// fetched during `generation`. The entries invalidated by the new generation only
+    /// include the entries cached before it started, so the row would otherwise outlive it.
+    async fn discard_stale(&self, key: &K, generation: u64) {
+        if self.generation.get() != generation {
+            self.cache.invalidate(key).await;
+        }
+    }
+
+    /// Synchronizes the generation of the cache with the source set with
+    /// [`RowCacheBuilder::generation_source`], and returns the current generation.
+    ///
+    /// If the generation of the source changed since the previous synchronization, a new
+    /// generation starts, like with [`RowCache::bump_generation`]. The first synchronization
+    /// only records the generation of the source. Without a source, this only returns the
+    /// current generation.
+    pub async fn sync_generation(&self) -> Result<u64, sqlx::Error>
+    where
+        i64: Type<DB> + for<'r> Decode<'r, DB>,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let Some(source) = &self.generation.source else {
+            return Ok(self.generation.get());
+        };
+        let generation = sqlx::query_scalar::<_, i64>(source.borrow())
+            .fetch_one(&self.pool)
+            .await?;
+        match self.generation.observe(generation) {
+            true => Ok(self.bump_generation()),
+            false => Ok(self.generation.get()),
+        }
+    }
+
+    /// Synchronizes the generation of the cache with its source every `period`.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime.
+    /// Failed synchronizations are retried on the next period (and reported as warnings with
+    /// the `tracing` feature).
+    pub async fn watch_generation(&self, period: Duration)
+    where
+        i64: Type<DB> + for<'r> Decode<'r, DB>,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _result = self.sync_generation().await;
+            #[cfg(feature = "tracing")]
+            if let Err(error) = _result {
+                tracing::warn!(cache = self.cache.name(), %error, "failed to sync the generation");
+            }
+        }
+    }
+
+    /// Reads the entries of the `InvalidationLog` set with
+    /// [`RowCacheBuilder::invalidation_log`] logged since the previous synchronization, and
+    /// invalidates the entries of their keys. Returns the number of entries read.
+    ///
+    /// The first synchronization starts from the end of the log and invalidates the whole
+    /// cache, as do synchronizations finding that the unread entries were pruned. The entries
+    /// are read in batches, sized with
+    /// [`InvalidationLog::batch_size`](crate::future::InvalidationLog::batch_size).
+    pub async fn sync_log(&self) -> Result<usize, sqlx::Error>
+    where
+        i64: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+        String: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let Some(log) = &self.log else { return Ok(0) };
+        let mut cursor = log.cursor.lock().await;
+        let (min, max): (Option<i64>, Option<i64>) = sqlx::query_as(log.bounds.borrow())
+            .fetch_one(&self.pool)
+            .await?;
+        // the entries of the lookback window are marked as read by the first synchronization
+        let first = cursor.last.is_none();
+        let last = cursor.last.or(max).unwrap_or_default();
+        let mut all = first || min.is_some_and(|min| min > last + 1);
+        let mut read = 0;
+        let mut after = last - log.lookback;
+        loop {
+            let entries: Vec<(i64, Option<String>)> = sqlx::query_as(log.tail.borrow())
+                .bind(log.table.to_string())
+                .bind(after)
+                .fetch_all(&self.pool)
+                .await?;
+            let batch = entries.len();
+            for (seq, key) in entries {
+                after = seq;
+                if !cursor.seen.insert(seq) || first {
+                    continue;
+                }
+                read += 1;
+                let parse = log.parse_key.as_ref().zip(key);
+                match parse.and_then(|(parse, key)| parse(&key)) {
+                    Some(key) if !all => {
+                        self.admit(&key);
+                        self.cache.invalidate(&key).await;
+                    }
+                    _ => all = true,
+                }
+            }
+            if batch < log.batch_size {
+                break;
+            }
+        }
+        if all {
+            self.reset_filter();
+            self.cache.invalidate_all();
+        }
+        let last = cursor.seen.last().copied().into_iter().chain(max).max();
+        let last = last.unwrap_or_default();
+        cursor.last = Some(last);
+        cursor.seen = cursor.seen.split_off(&(last - log.lookback));
+        Ok(read)
+    }
+
+    /// Synchronizes the cache with its `InvalidationLog` every `period`.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime.
+    /// Failed synchronizations are retried on the next period (and reported as warnings with
+    /// the `tracing` feature).
+    pub async fn watch_log(&self, period: Duration)
+    where
+        i64: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+        String: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _result = self.sync_log().await;
+            #[cfg(feature = "tracing")]
+            if let Err(error) = _result {
+                tracing::warn!(cache = self.cache.name(), %error, "failed to sync the log");
+            }
+        }
+    }
+
+    /// Rebuilds the filter set with [`RowCacheBuilder::existence_filter`] from a scan of the
+    /// keys of the table, and returns the number of keys scanned.
+    ///
+    /// The keys added to the filter during the scan are kept. If the filter is reset during
+    /// the scan, e.g. by a new generation, the rebuilt filter is discarded, as the scan may
+    /// predate the changes, and lookups are not filtered until the next rebuild. `0` is
+    /// returned then, as for caches without a filter.
+    pub async fn rebuild_filter(&self) -> Result<usize, sqlx::Error>
+    where
+        K: Type<DB> + for<'r> Decode<'r, DB> + Unpin,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let Some(filter) = &self.filter else {
+            return Ok(0);
+        };
+        let _rebuild = filter.rebuild.lock().await;
+        let mut args = DB::Arguments::default();
+        for bind in &self.binds {
+            bind(&mut args).map_err(sqlx::Error::Encode)?;
+        }
+        filter.start();
+        let keys: Vec<K> = sqlx::query_scalar_with(filter.scan.borrow(), args)
+            .fetch_all(&self.pool)
+            .await?;
+        if !filter.finish(&keys) {
+            return Ok(0);
+        }
+        Ok(keys.len())
+    }
+
+    /// Rebuilds the filter set with [`RowCacheBuilder::existence_filter`] every `period`,
+    /// starting immediately.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime.
+    /// Failed rebuilds are retried on the next period (and reported as warnings with the
+    /// `tracing` feature), while the previous filter is kept.
+    pub async fn watch_filter(&self, period: Duration)
+    where
+        K: Type<DB> + for<'r> Decode<'r, DB> + Unpin,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _result = self.rebuild_filter().await;
+            #[cfg(feature = "tracing")]
+            if let Err(error) = _result {
+                tracing::warn!(cache = self.cache.name(), %error, "failed to rebuild the filter");
+            }
+        }
+    }
+
+    /// Fetches the row of a key from the database and converts it to the cached value, and
+    /// returns it along with the generation during which it was fetched.
+    ///
+    /// If a new generation starts during the query, the row may predate the generation and
+    /// is fetched again, up to `MAX_FETCHES` times. The row of the last attempt is returned
+    /// otherwise, and is discarded by `discard_stale` once cached. The load first waits for a
+    /// slot if the loads are limited.
+    ///
+    /// With the `tracing` feature, the load is wrapped in a `row_cache.load` span recording
+    /// the duration of the query and whether a row was found.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "row_cache.load",
+        skip_all,
+        fields(cache = self.cache.name(), duration_ms, found),
+        err,
+    ))]
+    async fn load(&self, key: &K, priority: LoadPriority) -> Result<(Option<W>, u64), LoadError> {
+        let _permit = match &self.limiter {
+            Some(limiter) => Some(limiter.acquire(priority).await?),
+            None => None,
+        };
+        let mut fetches = 0;
+        loop {
+            let generation = self.generation.get();
+            let start = Instant::now();
+            let value = self.fetch(key).await?;
+            fetches += 1;
+            if let Some(early) = &self.early_refresh {
+                early.record(start.elapsed());
+            }
+            if self.generation.get() == generation || fetches == MAX_FETCHES {
+                if value.is_some() {
+                    self.admit(key);
+                }
+                return Ok((value, generation));
+            }
+        }
+    }
+
+    /// Fetches the row of a key from the database, binding the key followed by the values
+    /// of the filters configured on the builder, and converts it to the cached value.
+    async fn fetch(&self, key: &K) -> Result<Option<W>, sqlx::Error> {
+        let mut args = DB::Arguments::default();
+        (self.bind_key)(key, &mut args).map_err(sqlx::Error::Encode)?;
+        for bind in &self.binds {
+            bind(&mut args).map_err(sqlx::Error::Encode)?;
+        }
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let query = sqlx::query_as_with::<_, V, _>(self.query.borrow(), args);
+        let row = match &self.advisory_lock {
+            // the loads of the replicas are serialized rather than deduplicated, as each of
+            // them still queries the key once it holds the lock
+            Some(lock) => {
+                let id = AdvisoryLock::<DB>::id(self.cache.name(), key);
+                let deadline = Instant::now() + lock.timeout;
+                loop {
+                    let mut conn = LockingConnection::new(self.pool.acquire().await?);
+                    let mut tx = conn.begin().await?;
+                    if (lock.acquire)(&mut tx, id).await? {
+                        let row = query.fetch_optional(&mut *tx).await?;
+                        (lock.release)(&mut tx, id).await?;
+                        tx.commit().await?;
+                        conn.released();
+                        break row;
+                    }
+                    // the connection is returned to the pool rather than held while waiting
+                    tx.rollback().await?;
+                    conn.released();
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break query.fetch_optional(&self.pool).await?;
+                    }
+                    sleep(RETRY_INTERVAL.min(deadline - now)).await;
+                }
+            }
+            None => query.fetch_optional(&self.pool).await?,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::Span::current()
+            .record("duration_ms", start.elapsed().as_secs_f64() * 1000.0)
+            .record("found", row.is_some());
+        row.map(&self.map_row)
+            .transpose()
+            .map_err(sqlx::Error::Decode)
+    }
+}
+
+/// Records the outcome of a lookup in the current `row_cache.get` span.
+#[cfg(feature = "tracing")]
+fn record_outcome(fresh: bool, found: bool) {
+    let outcome = match (fresh, found) {
+        (true, _) => "miss",
+        (false, true) => "hit",
+        (false, false) => "null_hit",
+    };
+    tracing::Span::current().record("outcome", outcome);
+}
+
+impl<DB: Database, K, V, W, S> RowCache<DB, K, V, W, S>
+where
+    K: Hash + Eq + SSS,
+    W: Clone + SSS,
+    S: BuildHasher + Clone + SSS,
+{
+    /// Returns a snapshot of the statistics of the cache.
+    ///
+    /// The entry count and weighted size are approximate, as `moka` updates them lazily.
+    pub fn stats(&self) -> CacheStats {
+        self.counters.snapshot(&self.cache)
+    }
+
+    /// Returns the cached state of a key, along with the remaining time before it expires,
+    /// without querying the database.
+    ///
+    /// The entry is read like any other lookup, which resets its time-to-idle, but the read
+    /// is not counted in the statistics.
+    pub async fn inspect(&self, key: &K) -> EntryState<W>
+    where
+        K: Clone,
+    {
+        self.apply_changes().await;
+        self.probe.inspect(&self.cache, key).await
+    }
+
+    /// Invalidates the entries of the rows changed in the database since the last lookup,
+    /// as reported by [`RowCacheBuilder::invalidate_on_update`].
+    async fn apply_changes(&self) {
+        for change in self.changes.take() {
+            match change {
+                Change::Key(key) => {
+                    self.admit(&key);
+                    self.cache.invalidate(&key).await;
+                }
+                Change::All => {
+                    self.reset_filter();
+                    self.cache.invalidate_all();
+                }
+            }
+        }
+    }
+
+    /// Returns a receiver of the changes of the entries of the cache.
+    ///
+    /// Loads, refreshes and inserts are published by the methods of `RowCache`, while
+    /// invalidations and evictions are published when the cache removes the entries, like
+    /// the eviction listener. Entries inserted directly into the underlying
+    /// `moka::future::Cache` are not published.
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheEvent<K>> {
+        self.events.subscribe()
+    }
+
+    /// Inserts a value for a key, replacing its cached entry, if any.
+    ///
+    /// This shadows [`moka::future::Cache::insert`] to publish the insertion to the
+    /// subscribers of the cache.
+    ///
+    /// # Arguments
+    /// * `key` - The key to insert.
+    /// * `value` - The value to cache, or `None` to cache the absence of a row.
+    pub async fn insert(&self, key: K, value: Option<W>)
+    where
+        K: Clone,
+    {
+        self.upsert(key.clone(), value, EventCause::Insert).await;
+        self.broadcast(Some(&key));
+    }
+
+    /// Applies the change of a row, e.g. decoded from an external change data capture pipeline.
+    ///
+    /// Inserts and updates replace the entry of the row with its new value, like
+    /// [`RowCache::insert`] but without publishing it on the `InvalidationBus`, since every
+    /// replica applies the change itself, or invalidate it if the event does not carry the
+    /// new value. The entry of the previous key of the row is invalidated too if the key
+    /// changed. Deletes invalidate the entry of the row, and truncates invalidate the whole
+    /// cache.
+    ///
+    /// # Arguments
+    /// * `event` - The change to apply.
+    pub async fn apply_change(&self, event: &impl ChangeEvent<K, W>)
+    where
+        K: Clone,
+    {
+        match event.op() {
+            ChangeOp::Insert | ChangeOp::Update => {
+                let Some(key) = event.new_key() else {
+                    self.reset_filter();
+                    return self.cache.invalidate_all();
+                };
+                if let Some(old) = event.old_key()
+                    && old != key
+                {
+                    self.cache.invalidate(&old).await;
+                }
+                match event.new_value() {
+                    Some(value) => self.upsert(key, Some(value), EventCause::Insert).await,
+                    None => {
+                        self.admit(&key);
+                        self.cache.invalidate(&key).await;
+                    }
+                }
+            }
+            ChangeOp::Delete => match event.old_key() {
+                Some(key) => self.cache.invalidate(&key).await,
+                None => self.cache.invalidate_all(),
+            },
+            ChangeOp::Truncate => self.cache.invalidate_all(),
+        }
+    }
+
+    /// Replaces the cached entry of a key and publishes the change to the subscribers of the
+    /// cache, but not on the `InvalidationBus`.
+    pub(crate) async fn upsert(&self, key: K, value: Option<W>, cause: EventCause)
+    where
+        K: Clone,
+    {
+        self.upsert_with(key, value, cause, |_, _| false).await;
+    }
+
+    /// Replaces the cached entry of a key like `upsert`, invalidating the entries depending
+    /// on it unless `same` tells that the cached value and the new one are the same.
+    async fn upsert_with(
+        &self,
+        key: K,
+        value: Option<W>,
+        cause: EventCause,
+        same: impl FnOnce(&Option<W>, &Option<W>) -> bool,
+    ) where
+        K: Clone,
+    {
+        if value.is_some() {
+            self.admit(&key);
+        }
+        let mut old = Presence::Absent;
+        let mut unchanged = false;
+        let entry = self
+            .cache
+            .entry(key)
+            .and_upsert_with(|entry| {
+                if let Some(entry) = entry {
+                    old = Presence::of(entry.value());
+                    unchanged = same(entry.value(), &value);
+                }
+                async { value }
             })
-            .await
+            .await;
+        self.inserted(entry.key(), entry.value(), cause, old);
+        if !unchanged {
+            self.dependents.invalidate(entry.key()).await;
+        }
+    }
+
+    /// Invalidates the entry of a key, along with the entries of its replicas on the
+    /// `InvalidationBus` set with [`RowCacheBuilder::invalidation_bus`].
+    ///
+    /// # Arguments
+    /// * `key` - The key to invalidate.
+    pub async fn broadcast_invalidate(&self, key: &K) {
+        self.admit(key);
+        self.cache.invalidate(key).await;
+        self.broadcast(Some(key));
+    }
+
+    /// Invalidates all the entries of the cache, along with the entries of its replicas on
+    /// the `InvalidationBus` set with [`RowCacheBuilder::invalidation_bus`].
+    pub async fn broadcast_invalidate_all(&self) {
+        self.reset_filter();
+        self.cache.invalidate_all();
+        self.broadcast(None);
+    }
+
+    /// Queues the invalidation of a key (or of all the keys for `None`) for the replicas of
+    /// the cache, if it joined a bus, without waiting for the transport.
+    ///
+    /// Failures are reported as warnings with the `tracing` feature.
+    fn broadcast(&self, key: Option<&K>) {
+        let Some(link) = &self.bus else { return };
+        let key = key.map(&link.format_key);
+        let _result = link.bus.publish(&link.name, key.as_deref());
+        #[cfg(feature = "tracing")]
+        if let Err(error) = _result {
+            tracing::warn!(cache = %link.name, %error, "failed to broadcast an invalidation");
+        }
+    }
+
+    /// Adds a key whose row may have been created to the filter set with
+    /// [`RowCacheBuilder::existence_filter`], if any.
+    pub(crate) fn admit(&self, key: &K) {
+        if let Some(filter) = &self.filter {
+            filter.insert(key);
+        }
+    }
+
+    /// Stops filtering the lookups until the filter set with
+    /// [`RowCacheBuilder::existence_filter`] is rebuilt, as rows with unknown keys may have
+    /// been created.
+    pub(crate) fn reset_filter(&self) {
+        if let Some(filter) = &self.filter {
+            filter.reset();
+        }
+    }
+
+    /// Indexes the tags of a newly cached value and publishes its insertion.
+    fn inserted(&self, key: &K, value: &Option<W>, cause: EventCause, old: Presence)
+    where
+        K: Clone,
+    {
+        if let Some(tags) = &self.tags {
+            tags.insert(&Arc::new(key.clone()), value);
+        }
+        let new = Presence::of(value);
+        self.events
+            .publish(|| Arc::new(key.clone()), cause, old, new);
+    }
+
+    /// Returns the current generation of the cache.
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+
+    /// Starts a new generation, making all the cached entries stale, and returns it.
+    ///
+    /// The entries are invalidated with [`moka::future::Cache::invalidate_all`], which does
+    /// not walk the cache, and lookups already querying the database fetch their row again,
+    /// a few times at most, after which their row is returned without being cached.
+    /// With a [shared source](RowCacheBuilder::generation_source), update the source instead
+    /// so that all the replicas start a new generation.
+    pub fn bump_generation(&self) -> u64 {
+        let generation = self.generation.bump();
+        self.reset_filter();
+        self.cache.invalidate_all();
+        generation
+    }
+
+    /// Discards the cached entries of all the keys tagged with `tag`, as computed by the
+    /// function set with [`RowCacheBuilder::tags`].
+    ///
+    /// The keys are looked up in an index, without scanning the cache. The invalidations are
+    /// published and cascaded like the ones of [`moka::future::Cache::invalidate`].
+    ///
+    /// # Arguments
+    /// * `tag` - The tag to invalidate.
+    pub async fn invalidate_tag(&self, tag: &str) {
+        let Some(tags) = &self.tags else { return };
+        for key in tags.keys(tag) {
+            self.cache.invalidate(key.as_ref()).await;
+        }
+    }
+
+    /// Declares that the entries of this cache depend on the entries of another cache.
+    ///
+    /// Whenever an entry of `upstream` changes, i.e. it is invalidated, inserted with
+    /// [`RowCache::insert`] or reloaded with [`RowCache::refresh`], the entries of this cache
+    /// returned by `keys` are invalidated. The invalidations cascade in turn to the caches
+    /// depending on this cache. Evictions and loads of `upstream` do not cascade.
+    ///
+    /// For example, `permissions.depends_on(&users, |user: &UserId| Some(*user))` invalidates
+    /// the permissions of a user when the user changes.
+    ///
+    /// `upstream` keeps the underlying `moka::future::Cache` of this cache alive.
+    ///
+    /// # Arguments
+    /// * `upstream` - The cache this cache depends on.
+    /// * `keys` - Returns the keys of this cache depending on a key of `upstream`.
+    pub fn depends_on<DB2, K2, V2, W2, S2, I>(
+        &self,
+        upstream: &RowCache<DB2, K2, V2, W2, S2>,
+        keys: impl Fn(&K2) -> I + Send + Sync + 'static,
+    ) where
+        DB2: Database,
+        I: IntoIterator<Item = K>,
+    {
+        let cache = self.cache.clone();
+        upstream.dependents.add(move |key| {
+            let keys: Vec<K> = keys(key).into_iter().collect();
+            let cache = cache.clone();
+            Box::pin(async move {
+                for key in keys {
+                    cache.invalidate(&key).await;
+                }
+            })
+        });
+    }
+
+    /// Declares that all the entries of this cache depend on every entry of another cache.
+    ///
+    /// Whenever an entry of `upstream` changes, all the entries of this cache are
+    /// invalidated. See [`RowCache::depends_on`].
+    ///
+    /// For example, `permissions.depends_on_all(&roles)` invalidates all the permissions when
+    /// any role changes.
+    ///
+    /// # Arguments
+    /// * `upstream` - The cache this cache depends on.
+    pub fn depends_on_all<DB2, K2, V2, W2, S2>(&self, upstream: &RowCache<DB2, K2, V2, W2, S2>)
+    where
+        DB2: Database,
+    {
+        let cache = self.cache.clone();
+        upstream.dependents.add(move |_| {
+            cache.invalidate_all();
+            Box::pin(async {})
+        });
     }
 }
 
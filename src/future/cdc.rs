@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hash},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use send_sync_static::SSS;
+use sqlx::{PgPool, Postgres};
+
+use crate::future::{
+    builder::split_table,
+    cache::RowCache,
+    events::EventCause,
+    pgoutput::{PgOutputError, PgOutputMessage, Relation, TupleValue},
+    registry::BoxFuture,
+};
+
+/// A table, optionally qualified by its schema.
+type Table = (Option<Box<str>>, Box<str>);
+
+/// Applies the change of a row of a table to a cache.
+type Subscriber = Box<dyn Fn(&RowChange<'_>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// A row of a table, as sent by the `pgoutput` protocol.
+pub struct ChangedRow<'a> {
+    relation: &'a Relation,
+    values: &'a [TupleValue],
+    /// Whether only the key columns were sent, the others being `NULL`.
+    key_only: bool,
+}
+
+impl ChangedRow<'_> {
+    /// Returns the table of the row.
+    pub fn relation(&self) -> &Relation {
+        self.relation
+    }
+
+    /// Returns the value of a column in the text format of its type, if the column exists,
+    /// was sent and is not `NULL`.
+    pub fn get(&self, column: &str) -> Option<&str> {
+        let index = self
+            .relation
+            .columns
+            .iter()
+            .position(|c| c.name == column)?;
+        self.values.get(index).and_then(TupleValue::as_str)
+    }
+
+    /// Returns whether the values of all the columns were sent, i.e. no column is an unchanged
+    /// TOASTed value and the row is not limited to its key columns, like the previous rows
+    /// of the tables without `REPLICA IDENTITY FULL`.
+    pub fn is_complete(&self) -> bool {
+        !self.key_only && !self.values.contains(&TupleValue::Unchanged)
+    }
+}
+
+/// A change of a row, applied by the subscribers of a table.
+enum RowChange<'a> {
+    /// A row was inserted or updated, with its previous key if it changed.
+    Upsert {
+        old: Option<ChangedRow<'a>>,
+        new: ChangedRow<'a>,
+    },
+    /// A row was deleted.
+    Delete(ChangedRow<'a>),
+    /// A table was truncated.
+    Truncate(&'a Relation),
+    /// The changes of an undecodable message, which may have changed any table.
+    Unknown,
+}
+
+/// A change data capture feed applying the changes of PostgreSQL tables to `PgCache`s, from
+/// a logical replication slot using the `pgoutput` plugin.
+///
+/// Unlike `LISTEN`/`NOTIFY`, the changes are retained by the slot until they are applied, so
+/// that none are lost while the feed is not running, and their size is not limited. The
+/// slot is read with `pg_logical_slot_peek_binary_changes` and advanced once the changes are
+/// applied, so that the feed resumes from the last confirmed LSN after a restart.
+///
+/// The slot is created with [`PgChangeFeed::create_slot`], while the publication of the
+/// cached tables is created beforehand, e.g. with `CREATE PUBLICATION caches FOR TABLE users`.
+/// The database must run with `wal_level = logical`.
+///
+/// A slot retains the WAL until it is advanced, so a slot that is no longer read must be
+/// dropped with `pg_drop_replication_slot`.
+pub struct PgChangeFeed {
+    slot: Box<str>,
+    publication: Box<str>,
+    batch_size: u32,
+    subscribers: Vec<(Table, Subscriber)>,
+    relations: Mutex<HashMap<u32, Relation>>,
+    /// Serializes the synchronizations, so that the slot is advanced in order.
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl PgChangeFeed {
+    /// Creates a `PgChangeFeed` reading a replication slot, for the tables of a publication.
+    ///
+    /// # Arguments
+    /// * `slot` - The name of the logical replication slot.
+    /// * `publication` - The name of the publication of the cached tables.
+    pub fn new(slot: &str, publication: &str) -> Self {
+        PgChangeFeed {
+            slot: slot.into(),
+            publication: publication.into(),
+            batch_size: 1024,
+            subscribers: Vec::new(),
+            relations: Mutex::default(),
+            lock: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// Sets the number of changes read from the slot by each synchronization, rounded up to
+    /// whole transactions. Defaults to 1024.
+    pub fn batch_size(self, batch_size: u32) -> Self {
+        PgChangeFeed { batch_size, ..self }
+    }
+
+    /// Invalidates the cached entries of the changed rows of a table.
+    ///
+    /// # Arguments
+    /// * `cache` - The cache of the table.
+    /// * `table` - The name of the table, optionally qualified by its schema.
+    /// * `key` - Returns the key of a changed row, e.g. `|row| row.get("id")?.parse().ok()`.
+    ///   When it returns `None`, the whole cache is invalidated.
+    pub fn invalidate<K, V, W, S>(
+        self,
+        cache: &Arc<RowCache<Postgres, K, V, W, S>>,
+        table: &str,
+        key: impl Fn(&ChangedRow<'_>) -> Option<K> + Send + Sync + 'static,
+    ) -> Self
+    where
+        K: Clone + Hash + Eq + SSS,
+        V: SSS,
+        W: Clone + SSS,
+        S: BuildHasher + Clone + SSS,
+    {
+        let cache = Arc::clone(cache);
+        self.subscribe(table, move |change| {
+            // the keys to invalidate, if known
+            let keys: Option<Vec<K>> = match change {
+                RowChange::Upsert { old, new } => [old.as_ref(), Some(new)]
+                    .into_iter()
+                    .flatten()
+                    .map(&key)
+                    .collect(),
+                RowChange::Delete(old) => key(old).map(|key| vec![key]),
+                RowChange::Truncate(_) | RowChange::Unknown => None,
+            };
+            let cache = Arc::clone(&cache);
+            Box::pin(async move {
+                match keys {
+                    Some(keys) => {
+                        for key in keys {
+                            cache.admit(&key);
+                            cache.invalidate(&key).await;
+                        }
+                    }
+                    None => {
+                        cache.reset_filter();
+                        cache.invalidate_all();
+                    }
+                }
+            })
+        })
+    }
+
+    /// Updates the cached entries of the changed rows of a table in place, with the values
+    /// built from the rows sent by the replication slot, and invalidates the entries of the
+    /// deleted rows. The updates are not published on the `InvalidationBus` of the cache,
+    /// since the feed of every replica applies them itself.
+    ///
+    /// # Arguments
+    /// * `cache` - The cache of the table.
+    /// * `table` - The name of the table, optionally qualified by its schema.
+    /// * `key` - Returns the key of a changed row. When it returns `None`, the whole cache is
+    ///   invalidated.
+    /// * `value` - Builds the value cached for a changed row. When it returns `None`, e.g. for
+    ///   rows that are not [complete](ChangedRow::is_complete), the entry is invalidated.
+    pub fn update<K, V, W, S>(
+        self,
+        cache: &Arc<RowCache<Postgres, K, V, W, S>>,
+        table: &str,
+        key: impl Fn(&ChangedRow<'_>) -> Option<K> + Send + Sync + 'static,
+        value: impl Fn(&ChangedRow<'_>) -> Option<W> + Send + Sync + 'static,
+    ) -> Self
+    where
+        K: Clone + Hash + Eq + SSS,
+        V: SSS,
+        W: Clone + SSS,
+        S: BuildHasher + Clone + SSS,
+    {
+        let cache = Arc::clone(cache);
+        self.subscribe(table, move |change| {
+            // the keys to invalidate, if known, and the entry to update
+            let (keys, entry) = match change {
+                RowChange::Upsert { old, new } => {
+                    let entry = key(new).map(|key| (key, value(new)));
+                    let old = old.as_ref().map(&key);
+                    let keys = match (&entry, old) {
+                        (None, _) | (_, Some(None)) => None,
+                        (_, old) => Some(old.into_iter().flatten().collect()),
+                    };
+                    (keys, entry)
+                }
+                RowChange::Delete(old) => (key(old).map(|key| vec![key]), None),
+                RowChange::Truncate(_) | RowChange::Unknown => (None, None),
+            };
+            let cache = Arc::clone(&cache);
+            Box::pin(async move {
+                let Some(keys) = keys else {
+                    cache.reset_filter();
+                    cache.invalidate_all();
+                    return;
+                };
+                for key in keys {
+                    cache.invalidate(&key).await;
+                }
+                match entry {
+                    Some((key, Some(value))) => {
+                        cache.upsert(key, Some(value), EventCause::Insert).await
+                    }
+                    Some((key, None)) => {
+                        cache.admit(&key);
+                        cache.invalidate(&key).await;
+                    }
+                    None => {}
+                }
+            })
+        })
+    }
+
+    fn subscribe(
+        self,
+        table: &str,
+        subscriber: impl Fn(&RowChange<'_>) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    ) -> Self {
+        let mut feed = self;
+        let (schema, name) = split_table(table);
+        let table = (schema.map(Into::into), name.into());
+        feed.subscribers.push((table, Box::new(subscriber)));
+        feed
+    }
+
+    /// Creates the replication slot of the feed with the `pgoutput` plugin, if it does not
+    /// exist.
+    pub async fn create_slot(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "SELECT pg_create_logical_replication_slot($1, 'pgoutput') \
+            WHERE NOT EXISTS (SELECT FROM pg_replication_slots WHERE slot_name = $1)",
+        )
+        .bind(self.slot.as_ref())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Applies the changes retained by the replication slot to the caches, and advances the
+    /// slot past them. Returns the number of messages read.
+    ///
+    /// A message that cannot be decoded is skipped, and all the caches of the feed are
+    /// invalidated as its changes are unknown (and reported as a warning with the `tracing`
+    /// feature), so that the slot is still advanced past it.
+    pub async fn sync(&self, pool: &PgPool) -> Result<usize, sqlx::Error> {
+        let _guard = self.lock.lock().await;
+        let changes: Vec<(String, Vec<u8>)> = sqlx::query_as(
+            "SELECT lsn::text, data FROM pg_logical_slot_peek_binary_changes($1, NULL, $2, \
+            'proto_version', '1', 'publication_names', $3)",
+        )
+        .bind(self.slot.as_ref())
+        .bind(i32::try_from(self.batch_size).unwrap_or(i32::MAX))
+        .bind(self.publication.as_ref())
+        .fetch_all(pool)
+        .await?;
+        let Some((lsn, _)) = changes.last() else {
+            return Ok(0);
+        };
+        for (_, message) in &changes {
+            self.apply_or_invalidate(message).await;
+        }
+        sqlx::query("SELECT pg_replication_slot_advance($1, $2::pg_lsn)")
+            .bind(self.slot.as_ref())
+            .bind(lsn)
+            .execute(pool)
+            .await?;
+        Ok(changes.len())
+    }
+
+    /// Synchronizes the caches with the replication slot every `period`.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime.
+    /// Failed synchronizations are retried on the next period (and reported as warnings with
+    /// the `tracing` feature).
+    pub async fn watch(&self, pool: &PgPool, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _result = self.sync(pool).await;
+            #[cfg(feature = "tracing")]
+            if let Err(error) = _result {
+                tracing::warn!(slot = self.slot.as_ref(), %error, "failed to sync the change feed");
+            }
+        }
+    }
+
+    /// Applies a `pgoutput` message to the caches of its table, e.g. a message received on a
+    /// replication connection managed by the application.
+    ///
+    /// The `Relation` message of a table must be applied before the changes of its rows,
+    /// which are ignored otherwise.
+    pub async fn apply(&self, message: &[u8]) -> Result<(), PgOutputError> {
+        let message = PgOutputMessage::decode(message)?;
+        let updates: Vec<_> = {
+            let mut relations = self.relations.lock().unwrap();
+            if let PgOutputMessage::Relation(relation) = message {
+                relations.insert(relation.id, relation);
+                return Ok(());
+            }
+            let row = |relation, values, key_only| {
+                let relation = relations.get(&relation)?;
+                Some(ChangedRow {
+                    relation,
+                    values,
+                    key_only,
+                })
+            };
+            let changes = match &message {
+                PgOutputMessage::Insert { relation, new } => row(*relation, new, false)
+                    .map(|new| RowChange::Upsert { old: None, new })
+                    .into_iter()
+                    .collect(),
+                PgOutputMessage::Update {
+                    relation,
+                    old,
+                    key_only,
+                    new,
+                } => row(*relation, new, false)
+                    .map(|new| {
+                        let old = old.as_ref().and_then(|old| row(*relation, old, *key_only));
+                        RowChange::Upsert { old, new }
+                    })
+                    .into_iter()
+                    .collect(),
+                PgOutputMessage::Delete {
+                    relation,
+                    old,
+                    key_only,
+                } => row(*relation, old, *key_only)
+                    .map(RowChange::Delete)
+                    .into_iter()
+                    .collect(),
+                PgOutputMessage::Truncate { relations: ids } => ids
+                    .iter()
+                    .filter_map(|id| relations.get(id))
+                    .map(RowChange::Truncate)
+                    .collect(),
+                _ => Vec::new(),
+            };
+            changes
+                .iter()
+                .flat_map(|change| self.notify(change))
+                .collect()
+        };
+        for update in updates {
+            update.await;
+        }
+        Ok(())
+    }
+
+    /// Applies a `pgoutput` message, or invalidates all the caches of the feed if it cannot
+    /// be decoded.
+    pub(crate) async fn apply_or_invalidate(&self, message: &[u8]) {
+        let Err(_error) = self.apply(message).await else {
+            return;
+        };
+        #[cfg(feature = "tracing")]
+        tracing::warn!(slot = self.slot.as_ref(), error = %_error, "skipped an undecodable message");
+        for update in self.notify(&RowChange::Unknown) {
+            update.await;
+        }
+    }
+
+    /// Returns the updates of the caches of a table for the change of a row, or of all the
+    /// tables for an unknown change.
+    fn notify(&self, change: &RowChange<'_>) -> Vec<BoxFuture<'static, ()>> {
+        let relation = match change {
+            RowChange::Upsert { new: row, .. } | RowChange::Delete(row) => Some(row.relation),
+            RowChange::Truncate(relation) => Some(*relation),
+            RowChange::Unknown => None,
+        };
+        self.subscribers
+            .iter()
+            .filter(|((schema, name), _)| {
+                relation.is_none_or(|relation| {
+                    schema
+                        .as_deref()
+                        .is_none_or(|schema| schema == relation.namespace)
+                        && name.as_ref() == relation.name
+                })
+            })
+            .map(|(_, subscriber)| subscriber(change))
+            .collect()
+    }
+}
@@ -0,0 +1,44 @@
+/// A change of a row of a cached table, as produced by an external change data capture
+/// pipeline, and applied to a cache with
+/// [`RowCache::apply_change`](crate::future::RowCache::apply_change).
+///
+/// Implementations decode the events of a pipeline, e.g. `DebeziumEvent` for the JSON envelope
+/// of Debezium (with the `debezium` feature), and convert their rows to the keys and values of
+/// a cache.
+pub trait ChangeEvent<K, W> {
+    /// Returns the operation of the change.
+    fn op(&self) -> ChangeOp;
+
+    /// Returns the key of the row before the change, for updates and deletes.
+    ///
+    /// Returns `None` if the event does not carry it, in which case the whole cache is
+    /// invalidated by deletes.
+    fn old_key(&self) -> Option<K>;
+
+    /// Returns the key of the row after the change, for inserts and updates.
+    ///
+    /// Returns `None` if the event does not carry it, in which case the whole cache is
+    /// invalidated by inserts and updates.
+    fn new_key(&self) -> Option<K>;
+
+    /// Returns the row after the change converted to the cached value, for inserts and updates.
+    ///
+    /// Defaults to `None`, in which case the entry of the row is invalidated instead of
+    /// updated in place.
+    fn new_value(&self) -> Option<W> {
+        None
+    }
+}
+
+/// The operation of a [`ChangeEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    /// A row was inserted, or read by the snapshot of a pipeline.
+    Insert,
+    /// A row was updated.
+    Update,
+    /// A row was deleted.
+    Delete,
+    /// The table was truncated.
+    Truncate,
+}
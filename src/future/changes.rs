@@ -0,0 +1,58 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// A change of the rows of a cached table, reported by the database.
+pub(crate) enum Change<K> {
+    /// The row of a key changed.
+    Key(K),
+    /// Rows changed, but their keys are unknown.
+    All,
+}
+
+/// The maximum number of changes queued before they are collapsed into a single
+/// `Change::All`.
+pub(crate) const MAX_CHANGES: usize = 1024;
+
+/// The changes reported by the database since the last lookup of a `RowCache`.
+///
+/// Changes can be reported from synchronous callbacks, e.g. the hooks of a SQLite
+/// connection, so they are queued and applied to the cache by the next lookup. Bulk writes
+/// to a cache that is not looked up would grow the queue without bound, so beyond
+/// `MAX_CHANGES` it is collapsed into a single `Change::All`, which makes any other queued
+/// change redundant.
+pub(crate) struct Changes<K> {
+    /// Whether changes are queued, checked on every lookup before locking the queue.
+    pending: AtomicBool,
+    queue: Mutex<Vec<Change<K>>>,
+}
+
+impl<K> Default for Changes<K> {
+    fn default() -> Self {
+        Changes {
+            pending: AtomicBool::new(false),
+            queue: Mutex::default(),
+        }
+    }
+}
+
+impl<K> Changes<K> {
+    pub(crate) fn push(&self, change: Change<K>) {
+        let mut queue = self.queue.lock().unwrap();
+        if matches!(change, Change::All) || queue.len() >= MAX_CHANGES {
+            *queue = vec![Change::All];
+        } else if !matches!(queue.first(), Some(Change::All)) {
+            queue.push(change);
+        }
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// Takes the queued changes.
+    pub(crate) fn take(&self) -> Vec<Change<K>> {
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return Vec::new();
+        }
+        std::mem::take(&mut *self.queue.lock().unwrap())
+    }
+}
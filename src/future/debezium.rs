@@ -0,0 +1,130 @@
+use std::{fmt, marker::PhantomData};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde_json::{Map, Value};
+
+use crate::future::change::{ChangeEvent, ChangeOp};
+
+/// A change event in the JSON envelope of Debezium.
+///
+/// Both the envelope with a schema (`{"schema": ..., "payload": {...}}`) and the bare payload
+/// are accepted. The rows of the event are converted to the keys and values of a cache by
+/// [`DebeziumEvent::rows`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebeziumEvent {
+    /// The operation of the change.
+    pub op: ChangeOp,
+    /// The table of the changed row, qualified by its schema (or database) if the source
+    /// carries it.
+    pub table: Option<String>,
+    /// The row before the change, for updates and deletes.
+    pub before: Option<Map<String, Value>>,
+    /// The row after the change, for inserts and updates.
+    pub after: Option<Map<String, Value>>,
+}
+
+impl DebeziumEvent {
+    /// Decodes an event from its JSON envelope.
+    ///
+    /// Tombstones (`null` values following deletes on compacted topics) are rejected, as they
+    /// only repeat the key of the delete.
+    pub fn from_slice(json: &[u8]) -> Result<Self, serde_json::Error> {
+        let mut envelope: Map<String, Value> = serde_json::from_slice(json)?;
+        let mut payload = match envelope.remove("payload") {
+            Some(Value::Object(payload)) => payload,
+            Some(_) => return Err(serde_json::Error::custom("invalid `payload`")),
+            None => envelope,
+        };
+        let op = match payload.get("op").and_then(Value::as_str) {
+            Some("c" | "r") => ChangeOp::Insert,
+            Some("u") => ChangeOp::Update,
+            Some("d") => ChangeOp::Delete,
+            Some("t") => ChangeOp::Truncate,
+            Some(op) => return Err(serde_json::Error::custom(format!("unknown op `{op}`"))),
+            None => return Err(serde_json::Error::missing_field("op")),
+        };
+        let mut row = |field| match payload.remove(field) {
+            Some(Value::Object(row)) => Ok(Some(row)),
+            Some(Value::Null) | None => Ok(None),
+            Some(_) => Err(serde_json::Error::custom(format!("invalid `{field}`"))),
+        };
+        let (before, after) = (row("before")?, row("after")?);
+        let source = payload.get("source");
+        let field = |name| source.and_then(|source| source.get(name)?.as_str());
+        let table = field("table").map(|table| match field("schema").or(field("db")) {
+            Some(schema) => format!("{schema}.{table}"),
+            None => table.to_owned(),
+        });
+        Ok(DebeziumEvent {
+            op,
+            table,
+            before,
+            after,
+        })
+    }
+
+    /// Returns the change of the rows of the event, keyed by a column and deserialized as `V`,
+    /// to be applied with [`RowCache::apply_change`](crate::future::RowCache::apply_change).
+    ///
+    /// The key is deserialized from the value of `column`. Rows that cannot be deserialized as
+    /// `V` are invalidated instead of updated in place.
+    ///
+    /// # Arguments
+    /// * `column` - The key column of the table.
+    pub fn rows<V>(&self, column: &str) -> DebeziumRows<'_, V> {
+        DebeziumRows {
+            event: self,
+            column: column.into(),
+            _0: PhantomData,
+        }
+    }
+}
+
+/// The rows of a [`DebeziumEvent`], keyed by a column and deserialized as `V`.
+pub struct DebeziumRows<'a, V> {
+    event: &'a DebeziumEvent,
+    column: Box<str>,
+    _0: PhantomData<fn() -> V>,
+}
+
+impl<V> fmt::Debug for DebeziumRows<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DebeziumRows")
+            .field("event", self.event)
+            .field("column", &self.column)
+            .finish()
+    }
+}
+
+impl<V> DebeziumRows<'_, V> {
+    fn key<K: DeserializeOwned>(&self, row: Option<&Map<String, Value>>) -> Option<K> {
+        let key = row?.get(self.column.as_ref())?;
+        K::deserialize(key).ok()
+    }
+}
+
+impl<K, V, W> ChangeEvent<K, W> for DebeziumRows<'_, V>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    W: From<V>,
+{
+    fn op(&self) -> ChangeOp {
+        self.event.op
+    }
+
+    fn old_key(&self) -> Option<K> {
+        self.key(self.event.before.as_ref())
+    }
+
+    fn new_key(&self) -> Option<K> {
+        self.key(self.event.after.as_ref())
+    }
+
+    fn new_value(&self) -> Option<W> {
+        let row = self.event.after.clone()?;
+        serde_json::from_value::<V>(Value::Object(row))
+            .ok()
+            .map(W::from)
+    }
+}
@@ -0,0 +1,45 @@
+use std::{future::Future, sync::RwLock};
+
+use crate::future::registry::BoxFuture;
+
+/// Invalidates the entries of a dependent cache affected by the change of a key.
+type Cascade<K> = Box<dyn Fn(&K) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// The caches depending on the entries of a `RowCache`, as declared with
+/// [`RowCache::depends_on`](crate::future::RowCache::depends_on).
+pub(crate) struct Dependents<K> {
+    cascades: RwLock<Vec<Cascade<K>>>,
+}
+
+impl<K> Default for Dependents<K> {
+    fn default() -> Self {
+        Dependents {
+            cascades: RwLock::default(),
+        }
+    }
+}
+
+impl<K> Dependents<K> {
+    pub(crate) fn add(
+        &self,
+        cascade: impl Fn(&K) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    ) {
+        self.cascades.write().unwrap().push(Box::new(cascade));
+    }
+
+    /// Invalidates the entries of the dependent caches affected by the change of a key.
+    pub(crate) fn invalidate(&self, key: &K) -> impl Future<Output = ()> + Send + 'static {
+        let cascades: Vec<_> = self
+            .cascades
+            .read()
+            .unwrap()
+            .iter()
+            .map(|cascade| cascade(key))
+            .collect();
+        async move {
+            for cascade in cascades {
+                cascade.await;
+            }
+        }
+    }
+}
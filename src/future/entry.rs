@@ -0,0 +1,150 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use moka::{Expiry, future::Cache};
+use send_sync_static::SSS;
+
+/// The state of a key in a `RowCache`, as returned by [`RowCache::inspect`](crate::future::RowCache::inspect).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryState<W> {
+    /// The key is not cached, and the next lookup will query the database.
+    Absent,
+    /// The row of the key is cached.
+    Found {
+        /// The cached value.
+        value: W,
+        /// The remaining time before the entry expires, if it expires.
+        expires_in: Option<Duration>,
+    },
+    /// The absence of a row for the key is cached.
+    NotFound {
+        /// The remaining time before the entry expires, if it expires.
+        expires_in: Option<Duration>,
+    },
+}
+
+/// A boxed `Expiry` of the entries of a cache.
+pub(crate) type BoxExpiry<K, W> = Box<dyn Expiry<K, Option<W>> + Send + Sync>;
+
+/// Captures the expiry of an entry when it is read, as `moka` does not expose it otherwise.
+pub(crate) struct Probe<K> {
+    /// Serializes the inspections, so that the slot holds a single key.
+    lock: tokio::sync::Mutex<()>,
+    /// Whether an inspection is in progress, checked on every read before locking the slot.
+    active: AtomicBool,
+    slot: Mutex<Option<(K, Option<Read>)>>,
+}
+
+/// The expiry of an entry when it was read.
+struct Read {
+    last_modified_at: Instant,
+    duration_until_expiry: Option<Duration>,
+}
+
+impl<K> Default for Probe<K> {
+    fn default() -> Self {
+        Probe {
+            lock: tokio::sync::Mutex::default(),
+            active: AtomicBool::new(false),
+            slot: Mutex::default(),
+        }
+    }
+}
+
+impl<K: Clone + Hash + Eq + SSS> Probe<K> {
+    /// Reads the entry of a key and computes its remaining time before expiry.
+    ///
+    /// The entry is read like any other lookup, which resets its time-to-idle.
+    pub(crate) async fn inspect<W, S>(
+        &self,
+        cache: &Cache<K, Option<W>, S>,
+        key: &K,
+    ) -> EntryState<W>
+    where
+        W: Clone + SSS,
+        S: BuildHasher + Clone + SSS,
+    {
+        let _guard = self.lock.lock().await;
+        *self.slot.lock().unwrap() = Some((key.clone(), None));
+        self.active.store(true, Ordering::Release);
+        let value = cache.get(key).await;
+        self.active.store(false, Ordering::Release);
+        let read = self.slot.lock().unwrap().take().and_then(|(_, read)| read);
+        let (Some(value), Some(read)) = (value, read) else {
+            return EntryState::Absent;
+        };
+        let policy = cache.policy();
+        let ttl = policy
+            .time_to_live()
+            .map(|ttl| ttl.saturating_sub(read.last_modified_at.elapsed()));
+        let expires_in = [read.duration_until_expiry, ttl, policy.time_to_idle()]
+            .into_iter()
+            .flatten()
+            .min();
+        match value {
+            Some(value) => EntryState::Found { value, expires_in },
+            None => EntryState::NotFound { expires_in },
+        }
+    }
+}
+
+/// An `Expiry` forwarding to the expiry of the cache, and reporting reads to a `Probe`.
+pub(crate) struct Probed<K, W> {
+    pub(crate) expiry: BoxExpiry<K, W>,
+    pub(crate) probe: Arc<Probe<K>>,
+}
+
+impl<K: Eq, W> Expiry<K, Option<W>> for Probed<K, W> {
+    fn expire_after_create(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        created_at: Instant,
+    ) -> Option<Duration> {
+        self.expiry.expire_after_create(key, value, created_at)
+    }
+
+    fn expire_after_read(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        read_at: Instant,
+        duration_until_expiry: Option<Duration>,
+        last_modified_at: Instant,
+    ) -> Option<Duration> {
+        let duration_until_expiry = self.expiry.expire_after_read(
+            key,
+            value,
+            read_at,
+            duration_until_expiry,
+            last_modified_at,
+        );
+        if self.probe.active.load(Ordering::Acquire)
+            && let Some((probed, read)) = &mut *self.probe.slot.lock().unwrap()
+            && probed == key
+        {
+            *read = Some(Read {
+                last_modified_at,
+                duration_until_expiry,
+            });
+        }
+        duration_until_expiry
+    }
+
+    fn expire_after_update(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        updated_at: Instant,
+        duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.expiry
+            .expire_after_update(key, value, updated_at, duration_until_expiry)
+    }
+}
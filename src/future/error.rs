@@ -0,0 +1,80 @@
+use std::{error::Error, fmt};
+
+/// An error returned by [`RowCacheBuilder::try_build`](crate::future::RowCacheBuilder::try_build).
+#[derive(Debug)]
+pub enum BuildError {
+    /// The table of the cache does not exist.
+    TableNotFound(Box<str>),
+    /// The key column or a selected column does not exist in the table of the cache.
+    ColumnNotFound { table: Box<str>, column: Box<str> },
+    /// The cache has an existence filter while its keys are mapped with `map_key`, so the
+    /// keys scanned for the filter would not be the keys looked up.
+    MappedKeyFilter,
+    /// The database rejected the query, or could not be reached.
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::TableNotFound(table) => write!(f, "table `{table}` does not exist"),
+            BuildError::ColumnNotFound { table, column } => {
+                write!(f, "column `{column}` does not exist in table `{table}`")
+            }
+            BuildError::MappedKeyFilter => {
+                f.write_str("an existence filter cannot be used with mapped keys")
+            }
+            BuildError::Database(e) => write!(f, "failed to validate the query: {e}"),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for BuildError {
+    fn from(e: sqlx::Error) -> Self {
+        BuildError::Database(e)
+    }
+}
+
+/// An error returned by the lookups of a [`RowCache`](crate::future::RowCache).
+#[derive(Debug)]
+pub enum LoadError {
+    /// The load was shed because the database loads of the cache are saturated, as set with
+    /// [`RowCacheBuilder::max_concurrent_loads`](crate::future::RowCacheBuilder::max_concurrent_loads).
+    Overloaded,
+    /// The query failed, or the row could not be converted by
+    /// [`RowCacheBuilder::map_row`](crate::future::RowCacheBuilder::map_row).
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Overloaded => f.write_str("too many loads are waiting for the database"),
+            LoadError::Database(e) => write!(f, "failed to load the row: {e}"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Database(e) => Some(e),
+            LoadError::Overloaded => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for LoadError {
+    fn from(e: sqlx::Error) -> Self {
+        LoadError::Database(e)
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use moka::notification::RemovalCause;
+use tokio::sync::broadcast;
+
+/// A change of an entry of a `RowCache`, as published to the receivers returned by
+/// [`RowCache::subscribe`](crate::future::RowCache::subscribe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEvent<K> {
+    /// The key of the entry.
+    pub key: Arc<K>,
+    /// What changed the entry.
+    pub cause: EventCause,
+    /// The state of the entry before the change.
+    pub old: Presence,
+    /// The state of the entry after the change.
+    pub new: Presence,
+}
+
+/// What changed an entry of a `RowCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventCause {
+    /// The entry was loaded from the database by a lookup that missed.
+    Load,
+    /// The entry was reloaded from the database by `RowCache::refresh`.
+    Refresh,
+    /// The entry was inserted by `RowCache::insert`.
+    Insert,
+    /// The entry was invalidated, e.g. by `invalidate`, `remove` or `invalidate_all`.
+    Invalidate,
+    /// The entry was evicted by the cache, because it expired (`RemovalCause::Expired`) or
+    /// to make room for other entries (`RemovalCause::Size`).
+    Evict(RemovalCause),
+}
+
+/// The state of an entry of a `RowCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    /// The key is not cached.
+    Absent,
+    /// The row of the key is cached.
+    Found,
+    /// The absence of a row for the key is cached.
+    NotFound,
+}
+
+impl Presence {
+    /// Returns the state of a cached value.
+    pub(crate) fn of<W>(value: &Option<W>) -> Self {
+        match value {
+            Some(_) => Presence::Found,
+            None => Presence::NotFound,
+        }
+    }
+}
+
+/// The sender of the events of a `RowCache`.
+pub(crate) struct Events<K> {
+    sender: broadcast::Sender<CacheEvent<K>>,
+}
+
+impl<K> Events<K> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Events {
+            sender: broadcast::Sender::new(capacity),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<CacheEvent<K>> {
+        self.sender.subscribe()
+    }
+
+    /// Publishes an event, if anyone is listening.
+    pub(crate) fn publish(
+        &self,
+        key: impl FnOnce() -> Arc<K>,
+        cause: EventCause,
+        old: Presence,
+        new: Presence,
+    ) {
+        if self.sender.receiver_count() > 0 {
+            let key = key();
+            let _ = self.sender.send(CacheEvent {
+                key,
+                cause,
+                old,
+                new,
+            });
+        }
+    }
+
+    /// Publishes the removal of an entry reported to the eviction listener of the cache.
+    ///
+    /// Replacements are not published, as they are published by `RowCache::insert` and
+    /// `RowCache::refresh` with the new value.
+    pub(crate) fn removed<W>(&self, key: Arc<K>, value: &Option<W>, cause: RemovalCause) {
+        let cause = match cause {
+            RemovalCause::Explicit => EventCause::Invalidate,
+            RemovalCause::Replaced => return,
+            cause => EventCause::Evict(cause),
+        };
+        self.publish(|| key, cause, Presence::of(value), Presence::Absent);
+    }
+}
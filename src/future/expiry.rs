@@ -0,0 +1,289 @@
+use std::{
+    collections::HashSet,
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use moka::{Expiry, sync::Cache};
+use tokio::sync::Notify;
+
+use crate::future::entry::BoxExpiry;
+
+/// The options randomizing the expiry of the entries of a `RowCache`, as set with
+/// `RowCacheBuilder::ttl_jitter` and `RowCacheBuilder::early_refresh`.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ExpiryOptions {
+    /// The time-to-live of the cache, applied to every entry by `moka` too.
+    pub(crate) ttl: Option<Duration>,
+    /// The maximum fraction of the time-to-live of an entry cut at random.
+    pub(crate) jitter: f64,
+    /// The `beta` of the early refreshes, if enabled.
+    pub(crate) early_refresh: Option<f64>,
+}
+
+impl ExpiryOptions {
+    /// Wraps the expiry of a cache to apply the options, if any.
+    pub(crate) fn wrap<K, W>(
+        self,
+        expiry: BoxExpiry<K, W>,
+    ) -> (BoxExpiry<K, W>, Option<Arc<EarlyRefresh<K>>>)
+    where
+        K: Clone + Hash + Eq + Send + Sync + 'static,
+        W: 'static,
+    {
+        if self.jitter <= 0.0 && self.early_refresh.is_none() {
+            return (expiry, None);
+        }
+        let early = self
+            .early_refresh
+            .map(|beta| Arc::new(EarlyRefresh::new(beta)));
+        let randomized = Randomized {
+            expiry,
+            ttl: self.ttl,
+            jitter: self.jitter.clamp(0.0, 1.0),
+            early: early.clone(),
+        };
+        (Box::new(randomized), early)
+    }
+}
+
+/// Decides which lookups refresh their entry before it expires, following the XFetch
+/// algorithm of "Optimal Probabilistic Cache Stampede Prevention" (Vattani et al.).
+///
+/// A read refreshes its entry when `load_time * beta * -ln(random())` exceeds the remaining
+/// time of the entry, so that entries are refreshed by a single lookup shortly before they
+/// expire, the sooner the slower they are to load.
+pub(crate) struct EarlyRefresh<K> {
+    beta: f64,
+    /// The moving average of the load times, in nanoseconds.
+    load_time: AtomicU64,
+    /// Whether keys are due, checked on every lookup before locking the set.
+    pending: AtomicBool,
+    /// The keys due for a refresh, drawn by any read of their entry and forgotten with it.
+    pub(crate) due: Mutex<HashSet<K>>,
+    /// The due keys taken by lookups, waiting for `RowCache::run_early_refresh` to refresh
+    /// them, and forgotten with their entry too.
+    queued: Mutex<HashSet<K>>,
+    /// Wakes `RowCache::run_early_refresh` when keys are queued.
+    wake: Notify,
+}
+
+impl<K: Clone + Hash + Eq> EarlyRefresh<K> {
+    fn new(beta: f64) -> Self {
+        EarlyRefresh {
+            beta,
+            load_time: AtomicU64::new(0),
+            pending: AtomicBool::new(false),
+            due: Mutex::default(),
+            queued: Mutex::default(),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Records the time taken by a load, weighting the new sample by 1/8.
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let sample = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        let _ = self
+            .load_time
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                Some(match average {
+                    0 => sample,
+                    average => average - average / 8 + sample / 8,
+                })
+            });
+    }
+
+    /// Marks a key as due for a refresh if it loses the draw.
+    fn draw(&self, key: &K, remaining: Duration) {
+        let load_time = Duration::from_nanos(self.load_time.load(Ordering::Relaxed));
+        let gap = load_time.as_secs_f64() * self.beta * -(1.0 - random()).ln();
+        if gap >= remaining.as_secs_f64() {
+            self.due.lock().unwrap().insert(key.clone());
+            self.pending.store(true, Ordering::Release);
+        }
+    }
+
+    /// Returns whether the entry of a key is due for a refresh, clearing it.
+    fn take(&self, key: &K) -> bool {
+        if !self.pending.load(Ordering::Acquire) {
+            return false;
+        }
+        let mut due = self.due.lock().unwrap();
+        let taken = due.remove(key);
+        if due.is_empty() {
+            self.pending.store(false, Ordering::Release);
+        }
+        taken
+    }
+
+    /// Queues the refresh of the entry of a key if it is due, as looked up by `try_get`.
+    pub(crate) fn queue(&self, key: &K) {
+        if self.take(key) {
+            self.queued.lock().unwrap().insert(key.clone());
+            self.wake.notify_one();
+        }
+    }
+
+    /// Waits for keys to be queued, and takes them.
+    pub(crate) async fn next(&self) -> Vec<K> {
+        loop {
+            let keys: Vec<K> = self.queued.lock().unwrap().drain().collect();
+            if !keys.is_empty() {
+                return keys;
+            }
+            self.wake.notified().await;
+        }
+    }
+
+    /// Forgets a key whose entry was removed, e.g. drawn by a read that does not refresh
+    /// entries, so that the due and queued keys are bounded by the entries of the cache.
+    pub(crate) fn forget(&self, key: &K) {
+        self.take(key);
+        self.queued.lock().unwrap().remove(key);
+    }
+}
+
+/// An `Expiry` giving every entry its own time-to-live, shortened at random, and drawing
+/// early refreshes on reads.
+struct Randomized<K, W> {
+    expiry: BoxExpiry<K, W>,
+    ttl: Option<Duration>,
+    jitter: f64,
+    early: Option<Arc<EarlyRefresh<K>>>,
+}
+
+impl<K, W> Randomized<K, W> {
+    fn jittered(&self, duration: Option<Duration>) -> Option<Duration> {
+        let duration = duration.or(self.ttl)?;
+        Some(duration.mul_f64(1.0 - self.jitter * random()))
+    }
+}
+
+impl<K: Clone + Hash + Eq, W> Expiry<K, Option<W>> for Randomized<K, W> {
+    fn expire_after_create(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        created_at: Instant,
+    ) -> Option<Duration> {
+        self.jittered(self.expiry.expire_after_create(key, value, created_at))
+    }
+
+    fn expire_after_read(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        read_at: Instant,
+        duration_until_expiry: Option<Duration>,
+        last_modified_at: Instant,
+    ) -> Option<Duration> {
+        let duration_until_expiry = self.expiry.expire_after_read(
+            key,
+            value,
+            read_at,
+            duration_until_expiry,
+            last_modified_at,
+        );
+        if let Some((early, remaining)) = self.early.as_ref().zip(duration_until_expiry) {
+            early.draw(key, remaining);
+        }
+        duration_until_expiry
+    }
+
+    fn expire_after_update(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        updated_at: Instant,
+        duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        let updated =
+            self.expiry
+                .expire_after_update(key, value, updated_at, duration_until_expiry);
+        // an expiry kept by the wrapped expiry was already jittered
+        match updated == duration_until_expiry {
+            true => updated,
+            false => self.jittered(updated),
+        }
+    }
+}
+
+/// Returns a random number in `[0, 1)`.
+fn random() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    // every `RandomState` is seeded differently
+    let bits = RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (bits >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// The number of keys whose streak of misses is remembered by a `BackoffExpiry`.
+const STREAKS: u64 = 1 << 16;
+
+/// An `Expiry` caching `None`s for longer each time their key is missing again, as set with
+/// `RowCacheBuilder::time_to_live_for_none_backoff`.
+///
+/// The `n`-th consecutive `None` of a key is cached for `initial * 2^(n - 1)`, up to `max`.
+/// Caching a `Some` resets the streak of its key. `Some`s do not expire by themselves, like
+/// with the default expiry.
+pub(crate) struct BackoffExpiry<K> {
+    initial: Duration,
+    max: Duration,
+    /// The number of consecutive `None`s cached for the recently missing keys.
+    streaks: Cache<K, u32>,
+}
+
+impl<K: Hash + Eq + Send + Sync + 'static> BackoffExpiry<K> {
+    pub(crate) fn new(initial: Duration, max: Duration) -> Self {
+        BackoffExpiry {
+            initial,
+            max,
+            // a streak ends when the key has not been missing for a while
+            streaks: Cache::builder()
+                .max_capacity(STREAKS)
+                .time_to_idle(max.saturating_mul(2))
+                .build(),
+        }
+    }
+
+    fn expire<W>(&self, key: &K, value: &Option<W>) -> Option<Duration>
+    where
+        K: Clone,
+    {
+        if value.is_some() {
+            self.streaks.invalidate(key);
+            return None;
+        }
+        let streak = self.streaks.get(key).unwrap_or(0).saturating_add(1);
+        self.streaks.insert(key.clone(), streak);
+        let factor = 2_u32.saturating_pow(streak - 1);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
+
+impl<K, W> Expiry<K, Option<W>> for BackoffExpiry<K>
+where
+    K: Clone + Hash + Eq + Send + Sync + 'static,
+{
+    fn expire_after_create(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        self.expire(key, value)
+    }
+
+    fn expire_after_update(
+        &self,
+        key: &K,
+        value: &Option<W>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.expire(key, value)
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+/// The generation of the entries of a `RowCache`.
+///
+/// Entries loaded during an older generation are stale. The generation can follow a shared
+/// source, like a row of the database, so that all the replicas of a cache agree on it.
+pub(crate) struct Generation {
+    current: AtomicU64,
+    /// A query returning the generation of the shared source.
+    pub(crate) source: Option<Box<str>>,
+    /// The generation of the shared source when it was last synchronized.
+    seen: Mutex<Option<i64>>,
+}
+
+impl Generation {
+    pub(crate) fn new(source: Option<Box<str>>) -> Self {
+        Generation {
+            current: AtomicU64::new(0),
+            source,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.current.load(Ordering::Acquire)
+    }
+
+    /// Starts a new generation and returns it.
+    pub(crate) fn bump(&self) -> u64 {
+        self.current.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Records the generation of the shared source, and returns whether it changed since the
+    /// previous synchronization. The first observation is only a baseline, so that restarting
+    /// a replica does not start a new generation.
+    pub(crate) fn observe(&self, source: i64) -> bool {
+        let seen = self.seen.lock().unwrap().replace(source);
+        seen.is_some_and(|seen| seen != source)
+    }
+}
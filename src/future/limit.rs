@@ -0,0 +1,162 @@
+use std::{cmp::Reverse, sync::Mutex};
+
+use tokio::sync::oneshot;
+
+use crate::future::error::LoadError;
+
+/// The priority of a database load waiting for a slot of a `RowCache` whose loads are
+/// limited with `RowCacheBuilder::max_concurrent_loads`.
+///
+/// Waiting loads are started by decreasing priority, then in order of arrival. When the wait
+/// queue is full, a load sheds the most recent waiting load of a lower priority, if any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LoadPriority {
+    /// Loads that can be given up first, such as the early refreshes of entries.
+    Low,
+    /// The priority of the lookups made without a priority.
+    #[default]
+    Normal,
+    /// Loads that are given up last.
+    High,
+}
+
+/// Limits the number of concurrent database loads of a `RowCache`, queueing a bounded number
+/// of loads and shedding the others.
+pub(crate) struct LoadLimiter {
+    max: usize,
+    queue: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    running: usize,
+    /// The sequence number of the next waiting load.
+    next: u64,
+    waiting: Vec<Waiter>,
+}
+
+struct Waiter {
+    priority: LoadPriority,
+    seq: u64,
+    /// Tells the load whether it was started (`true`) or shed (`false`).
+    start: oneshot::Sender<bool>,
+}
+
+impl LoadLimiter {
+    pub(crate) fn new(max: usize, queue: usize) -> Self {
+        LoadLimiter {
+            max: max.max(1),
+            queue,
+            state: Mutex::new(State {
+                running: 0,
+                next: 0,
+                waiting: Vec::new(),
+            }),
+        }
+    }
+
+    /// Waits for a slot to load from the database, or fails right away if the queue is full
+    /// of loads of the same priority or higher.
+    pub(crate) async fn acquire(
+        &self,
+        priority: LoadPriority,
+    ) -> Result<LoadPermit<'_>, LoadError> {
+        let (start, receiver) = oneshot::channel();
+        let seq = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.max {
+                state.running += 1;
+                return Ok(LoadPermit { limiter: self });
+            }
+            if state.waiting.len() >= self.queue {
+                let lowest = state
+                    .waiting
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, waiter)| (waiter.priority, Reverse(waiter.seq)))
+                    .filter(|(_, waiter)| waiter.priority < priority)
+                    .map(|(index, _)| index);
+                let Some(lowest) = lowest else {
+                    return Err(LoadError::Overloaded);
+                };
+                let _ = state.waiting.swap_remove(lowest).start.send(false);
+            }
+            let seq = state.next;
+            state.next += 1;
+            state.waiting.push(Waiter {
+                priority,
+                seq,
+                start,
+            });
+            seq
+        };
+        let mut waiting = Waiting {
+            limiter: self,
+            seq,
+            receiver,
+            started: false,
+        };
+        match (&mut waiting.receiver).await {
+            Ok(true) => {
+                waiting.started = true;
+                Ok(LoadPermit { limiter: self })
+            }
+            _ => Err(LoadError::Overloaded),
+        }
+    }
+
+    /// Hands the slot of a finished load over to the waiting load of the highest priority.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(highest) = state
+            .waiting
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, waiter)| (waiter.priority, Reverse(waiter.seq)))
+            .map(|(index, _)| index)
+        {
+            // the load may have been cancelled
+            if state.waiting.swap_remove(highest).start.send(true).is_ok() {
+                return;
+            }
+        }
+        state.running -= 1;
+    }
+}
+
+/// A slot to load from the database, released when dropped.
+pub(crate) struct LoadPermit<'a> {
+    limiter: &'a LoadLimiter,
+}
+
+impl Drop for LoadPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// A load waiting for a slot, leaving the queue if it is cancelled.
+struct Waiting<'a> {
+    limiter: &'a LoadLimiter,
+    seq: u64,
+    receiver: oneshot::Receiver<bool>,
+    started: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.started {
+            return;
+        }
+        let mut state = self.limiter.state.lock().unwrap();
+        if let Some(index) = state.waiting.iter().position(|w| w.seq == self.seq) {
+            state.waiting.swap_remove(index);
+            return;
+        }
+        drop(state);
+        // the slot handed over to the cancelled load is handed over again
+        if self.receiver.try_recv() == Ok(true) {
+            self.limiter.release();
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::BTreeSet;
+
+use sqlx::{ColumnIndex, Database, Decode, Encode, Executor, IntoArguments, Pool, Type};
+
+use crate::future::{
+    builder::{Dialect, QueryBuilder, split_table},
+    registry::ParseKey,
+};
+
+/// A table logging the changes of the rows of cached tables, written by triggers and tailed
+/// by the caches.
+///
+/// Unlike polling an `updated_at` column, the log records deleted rows too. Each change is
+/// logged as a `(seq, table_name, row_key, op)` row, where `seq` is an increasing sequence
+/// number, `row_key` is the key of the changed row as text and `op` is `I`, `U` or `D`.
+///
+/// The log and its triggers are created with [`InvalidationLog::install`], and caches tail it
+/// with [`RowCache::sync_log`](crate::future::RowCache::sync_log) once they opt in with
+/// [`RowCacheBuilder::invalidation_log`](crate::future::RowCacheBuilder::invalidation_log).
+/// The log is shared by all the caches and replicas, and pruned with
+/// [`InvalidationLog::prune`].
+#[derive(Debug, Clone)]
+pub struct InvalidationLog {
+    table: Box<str>,
+    lookback: u32,
+    batch_size: u32,
+}
+
+impl InvalidationLog {
+    /// Creates an `InvalidationLog` stored in a table, optionally qualified by its schema.
+    pub fn new(table: &str) -> Self {
+        InvalidationLog {
+            table: table.into(),
+            lookback: 128,
+            batch_size: 1024,
+        }
+    }
+
+    /// Sets the number of sequence numbers below the last one read that are read again by
+    /// the caches. Defaults to 128.
+    ///
+    /// On PostgreSQL and MySQL, sequence numbers are allocated before the transactions
+    /// commit, so a change may be logged after changes with greater sequence numbers. Such
+    /// changes are still read if they fall within the lookback window.
+    pub fn lookback(self, lookback: u32) -> Self {
+        InvalidationLog { lookback, ..self }
+    }
+
+    /// Sets the maximum number of entries read by the caches per query. Defaults to 1024.
+    ///
+    /// Caches lagging behind read the log in as many batches as needed.
+    pub fn batch_size(self, batch_size: u32) -> Self {
+        let batch_size = batch_size.max(1);
+        InvalidationLog { batch_size, ..self }
+    }
+
+    /// Returns the name of the table of the log.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the statements creating the log, if it does not exist, and the triggers logging
+    /// the changes of the rows of a table.
+    ///
+    /// # Arguments
+    /// * `dialect` - The dialect of the database, e.g. `Dialect::of::<Postgres>()`.
+    /// * `table` - The name of the table to log, optionally qualified by its schema.
+    /// * `key` - The name of the key column of the table.
+    pub fn statements(&self, dialect: Dialect, table: &str, key: &str) -> Vec<String> {
+        let mut statements = vec![dialect.create_log(&self.table)];
+        statements.extend(dialect.log_triggers(&self.table, table, key));
+        statements
+    }
+
+    /// Creates the log, if it does not exist, and the triggers logging the changes of the rows
+    /// of a table. See [`InvalidationLog::statements`].
+    pub async fn install<DB>(
+        &self,
+        pool: &Pool<DB>,
+        table: &str,
+        key: &str,
+    ) -> Result<(), sqlx::Error>
+    where
+        DB: QueryBuilder + Database,
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    {
+        for statement in self.statements(DB::dialect(pool), table, key) {
+            sqlx::raw_sql(&statement).execute(pool).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes all but the last `keep` entries of the log.
+    ///
+    /// Caches lagging behind by more than `keep` entries detect the pruned entries and
+    /// invalidate all their entries.
+    pub async fn prune<DB>(&self, pool: &Pool<DB>, keep: u32) -> Result<(), sqlx::Error>
+    where
+        DB: QueryBuilder + Database,
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+        for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+        i64: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+        usize: ColumnIndex<DB::Row>,
+    {
+        let dialect = DB::dialect(pool);
+        let log = dialect.quote_table(&self.table);
+        let max = format!("SELECT MAX(seq) FROM {log}");
+        let max: Option<i64> = sqlx::query_scalar(&max).fetch_one(pool).await?;
+        let Some(max) = max else { return Ok(()) };
+        let delete = format!("DELETE FROM {log} WHERE seq <= {}", dialect.placeholder(1));
+        sqlx::query(&delete)
+            .bind(max - i64::from(keep))
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The position of a `RowCache` in an `InvalidationLog`.
+pub(crate) struct LogTail<K> {
+    /// The table logged for the cache.
+    pub(crate) table: Box<str>,
+    /// Returns the bounds of the sequence numbers of the log.
+    pub(crate) bounds: Box<str>,
+    /// Returns a batch of the entries of the table with a sequence number above a given one.
+    pub(crate) tail: Box<str>,
+    pub(crate) lookback: i64,
+    pub(crate) batch_size: usize,
+    pub(crate) parse_key: Option<ParseKey<K>>,
+    pub(crate) cursor: tokio::sync::Mutex<Cursor>,
+}
+
+/// The entries of an `InvalidationLog` read by a `RowCache`.
+#[derive(Default)]
+pub(crate) struct Cursor {
+    /// The greatest sequence number read, if the log was read.
+    pub(crate) last: Option<i64>,
+    /// The sequence numbers read within the lookback window.
+    pub(crate) seen: BTreeSet<i64>,
+}
+
+impl<K> LogTail<K> {
+    pub(crate) fn new(
+        log: InvalidationLog,
+        dialect: Dialect,
+        table: &str,
+        parse_key: Option<ParseKey<K>>,
+    ) -> Self {
+        let quoted = dialect.quote_table(&log.table);
+        LogTail {
+            table: table.into(),
+            bounds: format!("SELECT MIN(seq), MAX(seq) FROM {quoted}").into(),
+            tail: format!(
+                "SELECT seq, row_key FROM {quoted} WHERE table_name = {} AND seq > {} \
+                ORDER BY seq LIMIT {}",
+                dialect.placeholder(1),
+                dialect.placeholder(2),
+                log.batch_size
+            )
+            .into(),
+            lookback: log.lookback.into(),
+            batch_size: log.batch_size as usize,
+            parse_key,
+            cursor: tokio::sync::Mutex::default(),
+        }
+    }
+}
+
+/// Quotes a string literal.
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Returns the name of a trigger of a table, qualified by the schema of the table.
+fn trigger(log: &str, table: &str, op: &str) -> (Option<String>, String) {
+    let (schema, table) = split_table(table);
+    let name = format!("{}_{table}_{op}", split_table(log).1);
+    (schema.map(str::to_owned), name)
+}
+
+/// The statements of [`QueryBuilder::create_log`] and [`QueryBuilder::log_triggers`] for SQLite.
+pub(crate) mod sqlite {
+    use super::{literal, trigger};
+    use crate::future::builder::{QueryBuilder, split_table};
+
+    pub(crate) fn create_log<QB: QueryBuilder>(log: &str) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (seq INTEGER PRIMARY KEY AUTOINCREMENT, \
+            table_name TEXT NOT NULL, row_key TEXT, op TEXT NOT NULL)",
+            QB::quote_table(log)
+        )
+    }
+
+    pub(crate) fn log_triggers<QB: QueryBuilder>(log: &str, table: &str, key: &str) -> Vec<String> {
+        // the statements of a trigger only reference the tables of its schema, unqualified
+        let log_table = QB::quote(split_table(log).1);
+        let insert = |row: &str, op: &str| {
+            format!(
+                "INSERT INTO {log_table} (table_name, row_key, op) \
+                SELECT {}, CAST({row}.{} AS TEXT), '{op}'",
+                literal(table),
+                QB::quote(key)
+            )
+        };
+        let key = QB::quote(key);
+        let create = |op: &str, event: &str, body: String| {
+            let (schema, name) = trigger(log, table, op);
+            let name = match schema {
+                Some(schema) => format!("{}.{}", QB::quote(&schema), QB::quote(&name)),
+                None => QB::quote(&name),
+            };
+            format!(
+                "CREATE TRIGGER IF NOT EXISTS {name} AFTER {event} ON {} BEGIN {body}; END",
+                QB::quote(split_table(table).1)
+            )
+        };
+        vec![
+            create("insert", "INSERT", insert("NEW", "I")),
+            create(
+                "update",
+                "UPDATE",
+                format!(
+                    "{}; {} WHERE OLD.{key} IS NOT NEW.{key}",
+                    insert("NEW", "U"),
+                    insert("OLD", "U")
+                ),
+            ),
+            create("delete", "DELETE", insert("OLD", "D")),
+        ]
+    }
+}
+
+/// The statements of [`QueryBuilder::create_log`] and [`QueryBuilder::log_triggers`] for
+/// PostgreSQL.
+#[cfg(feature = "postgres")]
+pub(crate) mod postgres {
+    use super::{literal, trigger};
+    use crate::future::builder::QueryBuilder;
+
+    pub(crate) fn create_log<QB: QueryBuilder>(log: &str) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (seq BIGSERIAL PRIMARY KEY, \
+            table_name TEXT NOT NULL, row_key TEXT, op TEXT NOT NULL)",
+            QB::quote_table(log)
+        )
+    }
+
+    pub(crate) fn log_triggers<QB: QueryBuilder>(log: &str, table: &str, key: &str) -> Vec<String> {
+        let insert = |row: &str| {
+            format!(
+                "INSERT INTO {} (table_name, row_key, op) VALUES ({}, {row}.{}::text, left(TG_OP, 1));",
+                QB::quote_table(log),
+                literal(table),
+                QB::quote(key)
+            )
+        };
+        let key = QB::quote(key);
+        let (schema, name) = trigger(log, table, "changed");
+        let function = match &schema {
+            Some(schema) => format!("{}.{}", QB::quote(schema), QB::quote(&name)),
+            None => QB::quote(&name),
+        };
+        vec![
+            format!(
+                "CREATE OR REPLACE FUNCTION {function}() RETURNS trigger LANGUAGE plpgsql AS $$ \
+                BEGIN \
+                IF TG_OP <> 'DELETE' THEN {new} END IF; \
+                IF TG_OP = 'DELETE' THEN {old} \
+                ELSIF TG_OP = 'UPDATE' THEN IF OLD.{key} IS DISTINCT FROM NEW.{key} THEN {old} END IF; \
+                END IF; \
+                RETURN NULL; \
+                END $$",
+                new = insert("NEW"),
+                old = insert("OLD"),
+            ),
+            format!(
+                "CREATE OR REPLACE TRIGGER {} AFTER INSERT OR UPDATE OR DELETE ON {} \
+                FOR EACH ROW EXECUTE FUNCTION {function}()",
+                QB::quote(&name),
+                QB::quote_table(table)
+            ),
+        ]
+    }
+}
+
+/// The statements of [`QueryBuilder::create_log`] and [`QueryBuilder::log_triggers`] for MySQL.
+#[cfg(feature = "mysql")]
+pub(crate) mod mysql {
+    use super::{literal, trigger};
+    use crate::future::builder::QueryBuilder;
+
+    pub(crate) fn create_log<QB: QueryBuilder>(log: &str) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (seq BIGINT AUTO_INCREMENT PRIMARY KEY, \
+            table_name VARCHAR(255) NOT NULL, row_key VARCHAR(255), op CHAR(1) NOT NULL)",
+            QB::quote_table(log)
+        )
+    }
+
+    pub(crate) fn log_triggers<QB: QueryBuilder>(log: &str, table: &str, key: &str) -> Vec<String> {
+        let insert = |row: &str, op: &str| {
+            format!(
+                "INSERT INTO {} (table_name, row_key, op) VALUES ({}, CAST({row}.{} AS CHAR), '{op}');",
+                QB::quote_table(log),
+                literal(table),
+                QB::quote(key)
+            )
+        };
+        let key = QB::quote(key);
+        let create = |op: &str, event: &str, body: String| {
+            let (schema, name) = trigger(log, table, op);
+            let name = match schema {
+                Some(schema) => format!("{}.{}", QB::quote(&schema), QB::quote(&name)),
+                None => QB::quote(&name),
+            };
+            format!(
+                "CREATE TRIGGER IF NOT EXISTS {name} AFTER {event} ON {} FOR EACH ROW BEGIN {body} END",
+                QB::quote_table(table)
+            )
+        };
+        vec![
+            create("insert", "INSERT", insert("NEW", "I")),
+            create(
+                "update",
+                "UPDATE",
+                format!(
+                    "{} IF NOT (OLD.{key} <=> NEW.{key}) THEN {} END IF;",
+                    insert("NEW", "U"),
+                    insert("OLD", "U")
+                ),
+            ),
+            create("delete", "DELETE", insert("OLD", "D")),
+        ]
+    }
+}
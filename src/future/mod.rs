@@ -1,11 +1,66 @@
+#[cfg(feature = "admin")]
+mod admin;
+mod advisory;
+mod bloom;
 mod builder;
+mod bus;
 mod cache;
+#[cfg(feature = "postgres")]
+mod cdc;
+mod change;
+mod changes;
+#[cfg(feature = "debezium")]
+mod debezium;
+mod dependents;
+mod entry;
+mod error;
+mod events;
+mod expiry;
+mod generation;
+mod limit;
+mod log;
+#[cfg(feature = "postgres")]
+mod pgoutput;
+mod registry;
+mod schema;
+mod stats;
+mod tags;
+mod tenant;
 #[cfg(test)]
 mod test;
+#[cfg(feature = "sqlite")]
+mod update_hook;
 
 pub use {
-    builder::{QueryBuilder, RowCacheBuilder},
+    builder::{Dialect, QueryBuilder, RowCacheBuilder},
+    bus::{BusTransport, InvalidationBus, LocalTransport, TcpTransport},
     cache::RowCache,
+    change::{ChangeEvent, ChangeOp},
+    entry::EntryState,
+    error::{BuildError, LoadError},
+    events::{CacheEvent, EventCause, Presence},
+    limit::LoadPriority,
+    log::InvalidationLog,
+    registry::{CacheInfo, CacheRegistry, RegisteredCache},
+    schema::table_columns,
+    stats::CacheStats,
+    tenant::{TenantCache, TenantCacheBuilder},
+};
+
+#[cfg(feature = "admin")]
+pub use admin::AdminService;
+#[cfg(feature = "redis")]
+pub use bus::RedisTransport;
+#[cfg(feature = "debezium")]
+pub use debezium::{DebeziumEvent, DebeziumRows};
+#[cfg(feature = "deepsize")]
+pub use deepsize::{self, DeepSizeOf};
+#[cfg(feature = "sqlite")]
+pub use update_hook::UpdateHook;
+#[cfg(feature = "postgres")]
+pub use {
+    cdc::{ChangedRow, PgChangeFeed},
+    pgoutput::{PgOutputError, PgOutputMessage, Relation, RelationColumn, TupleValue},
 };
 
 #[cfg(feature = "mysql")]
@@ -19,6 +74,17 @@ mod mysql {
     impl QueryBuilder for MySql {
         const QUOTE: &str = "`";
         const PLACEHOLDER: &str = "?";
+        const TABLE_COLUMNS: &str = "SELECT column_name FROM information_schema.columns \
+            WHERE table_schema = COALESCE(?, DATABASE()) AND table_name = ? \
+            ORDER BY ordinal_position";
+
+        fn create_log(log: &str) -> String {
+            crate::future::log::mysql::create_log::<Self>(log)
+        }
+
+        fn log_triggers(log: &str, table: &str, key: &str) -> Vec<String> {
+            crate::future::log::mysql::log_triggers::<Self>(log, table, key)
+        }
     }
 
     pub type MySqlCache<K, V, W = Arc<V>, S = RandomState> = RowCache<MySql, K, V, W, S>;
@@ -36,6 +102,21 @@ mod postgres {
     impl QueryBuilder for Postgres {
         const QUOTE: &str = "\"";
         const PLACEHOLDER: &str = "$1";
+        const TABLE_COLUMNS: &str = "SELECT column_name::text FROM information_schema.columns \
+            WHERE table_schema = COALESCE($1, current_schema()) AND table_name = $2 \
+            ORDER BY ordinal_position";
+
+        fn placeholder(index: usize) -> String {
+            format!("${index}")
+        }
+
+        fn create_log(log: &str) -> String {
+            crate::future::log::postgres::create_log::<Self>(log)
+        }
+
+        fn log_triggers(log: &str, table: &str, key: &str) -> Vec<String> {
+            crate::future::log::postgres::log_triggers::<Self>(log, table, key)
+        }
     }
 
     pub type PgCache<K, V, W = Arc<V>, S = RandomState> = RowCache<Postgres, K, V, W, S>;
@@ -53,8 +134,43 @@ mod sqlite {
     impl QueryBuilder for Sqlite {
         const QUOTE: &str = "\"";
         const PLACEHOLDER: &str = "?";
+        const TABLE_COLUMNS: &str = "SELECT name FROM pragma_table_info(?2, ?1) ORDER BY cid";
     }
 
     pub type SqliteCache<K, V, W = Arc<V>, S = RandomState> = RowCache<Sqlite, K, V, W, S>;
     pub type SqliteCacheBuilder<K, V, W = Arc<V>> = RowCacheBuilder<Sqlite, K, V, W>;
 }
+
+#[cfg(feature = "any")]
+pub use any::*;
+#[cfg(feature = "any")]
+mod any {
+    use crate::future::{Dialect, QueryBuilder, RowCache, RowCacheBuilder};
+    use sqlx::{Any, Pool};
+    use std::{hash::RandomState, sync::Arc};
+
+    /// The constants describe a generic SQL database. The dialect of a pool is resolved from
+    /// the scheme of its URL, so that queries use the syntax of the database it connects to.
+    impl QueryBuilder for Any {
+        const QUOTE: &str = "\"";
+        const PLACEHOLDER: &str = "?";
+        const TABLE_COLUMNS: &str = "SELECT column_name FROM information_schema.columns \
+            WHERE table_schema = COALESCE(?, CURRENT_SCHEMA) AND table_name = ? \
+            ORDER BY ordinal_position";
+
+        fn dialect(pool: &Pool<Self>) -> Dialect {
+            match pool.connect_options().database_url.scheme() {
+                #[cfg(feature = "mysql")]
+                "mysql" | "mariadb" => Dialect::of::<sqlx::MySql>(),
+                #[cfg(feature = "postgres")]
+                "postgres" | "postgresql" => Dialect::of::<sqlx::Postgres>(),
+                #[cfg(feature = "sqlite")]
+                "sqlite" => Dialect::of::<sqlx::Sqlite>(),
+                _ => Dialect::of::<Self>(),
+            }
+        }
+    }
+
+    pub type AnyCache<K, V, W = Arc<V>, S = RandomState> = RowCache<Any, K, V, W, S>;
+    pub type AnyCacheBuilder<K, V, W = Arc<V>> = RowCacheBuilder<Any, K, V, W>;
+}
@@ -0,0 +1,305 @@
+use std::{error::Error, fmt};
+
+/// A message of the `pgoutput` logical replication protocol (version 1), as decoded by
+/// [`PgOutputMessage::decode`].
+///
+/// Only the messages describing row changes are decoded in full. See the
+/// [PostgreSQL documentation](https://www.postgresql.org/docs/current/protocol-logicalrep-message-formats.html)
+/// for the format of the messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgOutputMessage {
+    /// The start of a transaction.
+    Begin {
+        /// The LSN of the commit of the transaction.
+        final_lsn: u64,
+        /// The ID of the transaction.
+        xid: u32,
+    },
+    /// The end of a transaction.
+    Commit {
+        /// The LSN of the commit of the transaction.
+        commit_lsn: u64,
+        /// The LSN of the end of the transaction.
+        end_lsn: u64,
+    },
+    /// The description of a table, sent before the first change of the table.
+    Relation(Relation),
+    /// A row was inserted.
+    Insert {
+        /// The ID of the table.
+        relation: u32,
+        /// The inserted row.
+        new: Vec<TupleValue>,
+    },
+    /// A row was updated.
+    Update {
+        /// The ID of the table.
+        relation: u32,
+        /// The key columns of the previous row if they changed, or the previous row with
+        /// `REPLICA IDENTITY FULL`.
+        old: Option<Vec<TupleValue>>,
+        /// Whether `old` only holds the key columns (`K`), the others being `NULL`, rather
+        /// than the whole previous row (`O`).
+        key_only: bool,
+        /// The updated row.
+        new: Vec<TupleValue>,
+    },
+    /// A row was deleted.
+    Delete {
+        /// The ID of the table.
+        relation: u32,
+        /// The key columns of the deleted row, or the whole row with `REPLICA IDENTITY FULL`.
+        old: Vec<TupleValue>,
+        /// Whether `old` only holds the key columns (`K`), the others being `NULL`, rather
+        /// than the whole deleted row (`O`).
+        key_only: bool,
+    },
+    /// Tables were truncated.
+    Truncate {
+        /// The IDs of the tables.
+        relations: Vec<u32>,
+    },
+    /// Another message, identified by its type byte, e.g. `O` for origins or `Y` for types.
+    Other(u8),
+}
+
+/// The description of a table in the `pgoutput` protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relation {
+    /// The ID of the table.
+    pub id: u32,
+    /// The schema of the table.
+    pub namespace: String,
+    /// The name of the table.
+    pub name: String,
+    /// The columns of the table, in the order of the values of its rows.
+    pub columns: Vec<RelationColumn>,
+}
+
+/// A column of a [`Relation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationColumn {
+    /// The name of the column.
+    pub name: String,
+    /// Whether the column is part of the replica identity (usually the primary key).
+    pub key: bool,
+    /// The OID of the type of the column.
+    pub type_oid: u32,
+}
+
+/// The value of a column of a row in the `pgoutput` protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TupleValue {
+    /// The value is `NULL`, or not sent for the non-key columns of a key.
+    Null,
+    /// The value is a TOASTed value that did not change, and is not sent.
+    Unchanged,
+    /// The value in the text format of its type.
+    Text(String),
+}
+
+impl TupleValue {
+    /// Returns the value in the text format of its type, if it was sent and is not `NULL`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            TupleValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// An error returned when a `pgoutput` message cannot be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgOutputError {
+    /// The message ended before its end.
+    UnexpectedEnd,
+    /// The message contains an unknown byte, like an unknown kind of column value.
+    Unexpected(u8),
+    /// A string of the message is not valid UTF-8.
+    InvalidString,
+}
+
+impl fmt::Display for PgOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgOutputError::UnexpectedEnd => write!(f, "unexpected end of pgoutput message"),
+            PgOutputError::Unexpected(byte) => {
+                write!(
+                    f,
+                    "unexpected byte `{}` in pgoutput message",
+                    byte.escape_ascii()
+                )
+            }
+            PgOutputError::InvalidString => write!(f, "invalid UTF-8 in pgoutput message"),
+        }
+    }
+}
+
+impl Error for PgOutputError {}
+
+impl PgOutputMessage {
+    /// Decodes a `pgoutput` message, e.g. returned by
+    /// `pg_logical_slot_peek_binary_changes` or received on a replication connection.
+    pub fn decode(message: &[u8]) -> Result<Self, PgOutputError> {
+        let mut reader = Reader(message);
+        Ok(match reader.u8()? {
+            b'B' => {
+                let final_lsn = reader.u64()?;
+                let _commit_time = reader.u64()?;
+                let xid = reader.u32()?;
+                PgOutputMessage::Begin { final_lsn, xid }
+            }
+            b'C' => {
+                let _flags = reader.u8()?;
+                let commit_lsn = reader.u64()?;
+                let end_lsn = reader.u64()?;
+                PgOutputMessage::Commit {
+                    commit_lsn,
+                    end_lsn,
+                }
+            }
+            b'R' => {
+                let id = reader.u32()?;
+                let namespace = reader.string()?;
+                let name = reader.string()?;
+                let _replica_identity = reader.u8()?;
+                let columns = (0..reader.u16()?)
+                    .map(|_| {
+                        let flags = reader.u8()?;
+                        let name = reader.string()?;
+                        let type_oid = reader.u32()?;
+                        let _type_modifier = reader.u32()?;
+                        Ok(RelationColumn {
+                            name,
+                            key: flags & 1 == 1,
+                            type_oid,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                PgOutputMessage::Relation(Relation {
+                    id,
+                    namespace,
+                    name,
+                    columns,
+                })
+            }
+            b'I' => {
+                let relation = reader.u32()?;
+                reader.expect(b'N')?;
+                let new = reader.tuple()?;
+                PgOutputMessage::Insert { relation, new }
+            }
+            b'U' => {
+                let relation = reader.u32()?;
+                let (old, key_only) = match reader.u8()? {
+                    kind @ (b'K' | b'O') => {
+                        let old = reader.tuple()?;
+                        reader.expect(b'N')?;
+                        (Some(old), kind == b'K')
+                    }
+                    b'N' => (None, false),
+                    byte => return Err(PgOutputError::Unexpected(byte)),
+                };
+                let new = reader.tuple()?;
+                PgOutputMessage::Update {
+                    relation,
+                    old,
+                    key_only,
+                    new,
+                }
+            }
+            b'D' => {
+                let relation = reader.u32()?;
+                let key_only = match reader.u8()? {
+                    kind @ (b'K' | b'O') => kind == b'K',
+                    byte => return Err(PgOutputError::Unexpected(byte)),
+                };
+                let old = reader.tuple()?;
+                PgOutputMessage::Delete {
+                    relation,
+                    old,
+                    key_only,
+                }
+            }
+            b'T' => {
+                let count = reader.u32()?;
+                let _options = reader.u8()?;
+                let relations = (0..count).map(|_| reader.u32()).collect::<Result<_, _>>()?;
+                PgOutputMessage::Truncate { relations }
+            }
+            byte => PgOutputMessage::Other(byte),
+        })
+    }
+}
+
+/// Reads the fields of a message, in network byte order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PgOutputError> {
+        if self.0.len() < len {
+            return Err(PgOutputError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], PgOutputError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, PgOutputError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PgOutputError> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, PgOutputError> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, PgOutputError> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), PgOutputError> {
+        match self.u8()? {
+            byte if byte == expected => Ok(()),
+            byte => Err(PgOutputError::Unexpected(byte)),
+        }
+    }
+
+    /// Reads a null-terminated string.
+    fn string(&mut self) -> Result<String, PgOutputError> {
+        let len = self
+            .0
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(PgOutputError::UnexpectedEnd)?;
+        let bytes = self.bytes(len + 1)?;
+        let string = std::str::from_utf8(&bytes[..len]);
+        string
+            .map(str::to_owned)
+            .map_err(|_| PgOutputError::InvalidString)
+    }
+
+    /// Reads the values of the columns of a row.
+    fn tuple(&mut self) -> Result<Vec<TupleValue>, PgOutputError> {
+        (0..self.u16()?)
+            .map(|_| match self.u8()? {
+                b'n' => Ok(TupleValue::Null),
+                b'u' => Ok(TupleValue::Unchanged),
+                b't' => {
+                    let len = self.u32()? as usize;
+                    let text = std::str::from_utf8(self.bytes(len)?);
+                    let text = text.map_err(|_| PgOutputError::InvalidString)?;
+                    Ok(TupleValue::Text(text.to_owned()))
+                }
+                byte => Err(PgOutputError::Unexpected(byte)),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    future::Future,
+    hash::{BuildHasher, Hash},
+    pin::Pin,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use moka::future::Cache;
+use send_sync_static::SSS;
+
+use crate::future::{
+    entry::{EntryState, Probe},
+    stats::{CacheStats, Counters},
+};
+
+/// The fraction of an even split of the budget every registered cache is guaranteed, e.g.
+/// caches of priority 0 registered next to caches of higher priorities.
+const MIN_SHARE_DIVISOR: u64 = 10;
+
+/// A registry of `RowCache`s, which can be inspected, e.g. by an `AdminService`, and can
+/// share a global memory budget.
+///
+/// Caches join a registry with [`RowCacheBuilder::register`](crate::future::RowCacheBuilder::register),
+/// which keeps their own max capacity, or with
+/// [`RowCacheBuilder::share_budget`](crate::future::RowCacheBuilder::share_budget), along
+/// with a priority. The budget is split into one share per cache sharing it, proportionally
+/// to the priorities at first. [`CacheRegistry::rebalance`] then moves capacity towards the
+/// caches that make the best use of it, i.e. those with high priorities and high hit ratios
+/// since the previous rebalance.
+///
+/// `moka` caches cannot be resized, so every cache sharing the budget is built with the whole
+/// budget as its max capacity, and the weight of its entries is scaled by `budget / share`. A
+/// new share therefore applies to the entries inserted after the rebalance, while
+/// [`CacheRegistry::trim`] evicts the entries of the caches holding more than their share,
+/// e.g. after their share shrank.
+pub struct CacheRegistry {
+    budget: u64,
+    next_id: AtomicU64,
+    caches: Mutex<Vec<Registered>>,
+}
+
+/// The description of a cache in a `CacheRegistry`, as listed by [`CacheRegistry::caches`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheInfo {
+    /// The name of the cache, if any.
+    pub name: Option<String>,
+    /// The priority the cache registered with, if it shares the budget.
+    pub priority: Option<u32>,
+    /// The share of the budget currently allotted to the cache, if it shares the budget.
+    pub capacity: Option<u64>,
+    /// The weighted size of the entries of the cache, measured against `capacity`, if it
+    /// shares the budget.
+    pub usage: Option<u64>,
+    /// The statistics of the cache. For caches sharing the budget, its weighted size is
+    /// scaled like the weights of its entries, so it is measured against the whole budget
+    /// rather than `capacity`.
+    pub stats: CacheStats,
+}
+
+/// A handle to a cache in a `CacheRegistry`, as returned by [`CacheRegistry::cache`].
+///
+/// Keys are given as strings and parsed with the parser set by
+/// [`RowCacheBuilder::key_parser`](crate::future::RowCacheBuilder::key_parser).
+#[derive(Clone)]
+pub struct RegisteredCache {
+    /// The priority and the share of the cache, if it shares the budget.
+    share: Option<(u32, Arc<Share>)>,
+    inner: Arc<dyn Inspect>,
+}
+
+/// A cache in a `CacheRegistry`.
+struct Registered {
+    id: u64,
+    cache: RegisteredCache,
+    /// The lookup counters at the previous rebalance.
+    last: (u64, u64),
+}
+
+/// The share of the budget allotted to a registered cache, and the weight of its entries.
+#[derive(Default)]
+pub(crate) struct Share {
+    capacity: AtomicU64,
+    /// The unscaled weighted size of the entries of the cache.
+    usage: AtomicU64,
+}
+
+impl Share {
+    pub(crate) fn capacity(&self) -> u64 {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Records the insertion of an entry of a given (unscaled) weight.
+    pub(crate) fn add(&self, weight: u32) {
+        self.usage.fetch_add(weight.into(), Ordering::Relaxed);
+    }
+
+    /// Records the removal of an entry of a given (unscaled) weight.
+    pub(crate) fn remove(&self, weight: u32) {
+        let _ = self
+            .usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                Some(usage.saturating_sub(weight.into()))
+            });
+    }
+
+    fn usage(&self) -> u64 {
+        self.usage.load(Ordering::Relaxed)
+    }
+}
+
+/// Parses the keys of a cache from the admin endpoint or an `InvalidationLog`.
+pub(crate) type ParseKey<K> = Arc<dyn Fn(&str) -> Option<K> + Send + Sync>;
+
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A type-erased view of a registered cache. See [`RegisteredCache`].
+pub(crate) trait Inspect: Send + Sync {
+    fn name(&self) -> Option<&str>;
+    fn stats(&self) -> CacheStats;
+    fn inspect<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<EntryState<()>>>;
+    fn invalidate<'a>(&'a self, key: &'a str) -> BoxFuture<'a, bool>;
+    fn invalidate_all(&self);
+    fn trim<'a>(&'a self, share: &'a Share) -> BoxFuture<'a, ()>;
+}
+
+/// The view of a registered `RowCache`, sharing its underlying cache, counters and probe.
+pub(crate) struct Inspector<K, W, S> {
+    pub(crate) cache: Cache<K, Option<W>, S>,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) probe: Arc<Probe<K>>,
+    pub(crate) parse_key: Option<ParseKey<K>>,
+}
+
+impl<K, W, S> Inspector<K, W, S> {
+    fn parse_key(&self, key: &str) -> Option<K> {
+        self.parse_key.as_ref().and_then(|parse| parse(key))
+    }
+}
+
+impl<K, W, S> Inspect for Inspector<K, W, S>
+where
+    K: Clone + Hash + Eq + SSS,
+    W: Clone + SSS,
+    S: BuildHasher + Clone + SSS,
+{
+    fn name(&self) -> Option<&str> {
+        self.cache.name()
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.snapshot(&self.cache)
+    }
+
+    fn inspect<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<EntryState<()>>> {
+        Box::pin(async move {
+            let key = self.parse_key(key)?;
+            Some(match self.probe.inspect(&self.cache, &key).await {
+                EntryState::Absent => EntryState::Absent,
+                EntryState::Found { expires_in, .. } => EntryState::Found {
+                    value: (),
+                    expires_in,
+                },
+                EntryState::NotFound { expires_in } => EntryState::NotFound { expires_in },
+            })
+        })
+    }
+
+    fn invalidate<'a>(&'a self, key: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            let Some(key) = self.parse_key(key) else {
+                return false;
+            };
+            self.cache.invalidate(&key).await;
+            true
+        })
+    }
+
+    fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+
+    fn trim<'a>(&'a self, share: &'a Share) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if share.usage() <= share.capacity() {
+                return;
+            }
+            let keys: Vec<Arc<K>> = self.cache.iter().map(|(key, _)| key).collect();
+            for key in keys {
+                if share.usage() <= share.capacity() {
+                    break;
+                }
+                self.cache.invalidate(key.as_ref()).await;
+            }
+        })
+    }
+}
+
+/// The membership of a `RowCache` in a `CacheRegistry`, which is left on drop.
+pub(crate) struct Registration {
+    registry: Weak<CacheRegistry>,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry.caches.lock().unwrap().retain(|c| c.id != self.id);
+            registry.rebalance();
+        }
+    }
+}
+
+impl RegisteredCache {
+    /// Returns the description of the cache.
+    pub fn info(&self) -> CacheInfo {
+        CacheInfo {
+            name: self.inner.name().map(str::to_owned),
+            priority: self.share.as_ref().map(|(priority, _)| *priority),
+            capacity: self.share.as_ref().map(|(_, share)| share.capacity()),
+            usage: self.share.as_ref().map(|(_, share)| share.usage()),
+            stats: self.inner.stats(),
+        }
+    }
+
+    /// Returns the cached state of a key, without its value. See [`RowCache::inspect`](crate::future::RowCache::inspect).
+    ///
+    /// Returns `None` if the key cannot be parsed.
+    pub async fn inspect(&self, key: &str) -> Option<EntryState<()>> {
+        self.inner.inspect(key).await
+    }
+
+    /// Discards the cached entry of a key, if any.
+    ///
+    /// Returns `false` if the key cannot be parsed.
+    pub async fn invalidate(&self, key: &str) -> bool {
+        self.inner.invalidate(key).await
+    }
+
+    /// Discards all the cached entries.
+    pub fn invalidate_all(&self) {
+        self.inner.invalidate_all();
+    }
+}
+
+impl CacheRegistry {
+    /// Creates a new `CacheRegistry`.
+    ///
+    /// # Arguments
+    /// * `budget` - The total weighted size shared by the registered caches. With caches
+    ///   weighed by `RowCacheBuilder::weigh_by_memory`, this is a memory budget in bytes.
+    pub fn new(budget: u64) -> Arc<Self> {
+        Arc::new(CacheRegistry {
+            budget,
+            next_id: AtomicU64::new(0),
+            caches: Mutex::default(),
+        })
+    }
+
+    /// Returns the total weighted size shared by the caches sharing the budget.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Lists the registered caches with their current share and statistics.
+    pub fn caches(&self) -> Vec<CacheInfo> {
+        let caches = self.caches.lock().unwrap();
+        caches.iter().map(|c| c.cache.info()).collect()
+    }
+
+    /// Returns the first registered cache named `name`, if any.
+    pub fn cache(&self, name: &str) -> Option<RegisteredCache> {
+        let caches = self.caches.lock().unwrap();
+        let cache = caches.iter().find(|c| c.cache.inner.name() == Some(name))?;
+        Some(cache.cache.clone())
+    }
+
+    /// Splits the budget between the caches sharing it.
+    ///
+    /// Each cache is guaranteed a tenth of an even split of the budget, so that no cache is
+    /// left without capacity. The rest of the budget is split between the caches
+    /// proportionally to their score, `priority * (1 + hit ratio)`, where the hit ratio only
+    /// covers the lookups made since the previous rebalance. A cache of priority 0 therefore
+    /// only gets the guaranteed share, unless all the priorities are 0, in which case the
+    /// budget is split evenly.
+    ///
+    /// The caches holding more than their new share are only trimmed by
+    /// [`CacheRegistry::trim`].
+    pub fn rebalance(&self) {
+        let mut caches = self.caches.lock().unwrap();
+        let mut caches: Vec<_> = caches
+            .iter_mut()
+            .filter(|c| c.cache.share.is_some())
+            .collect();
+        let scores: Vec<f64> = caches
+            .iter_mut()
+            .map(|c| {
+                let priority = c.cache.share.as_ref().map_or(0, |(priority, _)| *priority);
+                let stats = c.cache.inner.stats();
+                let recent = CacheStats {
+                    hits: stats.hits - c.last.0,
+                    misses: stats.misses - c.last.1,
+                    ..stats
+                };
+                c.last = (stats.hits, stats.misses);
+                priority as f64 * (1.0 + recent.hit_ratio())
+            })
+            .collect();
+        let total: f64 = scores.iter().sum();
+        let count = caches.len() as u64;
+        let floor = self.budget / count.max(1) / MIN_SHARE_DIVISOR;
+        let rest = self.budget - floor * count;
+        for (cache, score) in caches.iter().zip(scores) {
+            let share = match total {
+                0.0 => self.budget / count,
+                total => floor + (rest as f64 * score / total) as u64,
+            };
+            if let Some((_, shared)) = &cache.cache.share {
+                shared.capacity.store(share, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Evicts entries from the caches holding more than their share of the budget, until
+    /// they fit in it.
+    ///
+    /// `moka` cannot evict entries on demand, so the entries are invalidated, in no
+    /// particular order, and their invalidations are published and cascaded like the ones
+    /// of [`moka::future::Cache::invalidate`].
+    pub async fn trim(&self) {
+        let caches: Vec<RegisteredCache> = {
+            let caches = self.caches.lock().unwrap();
+            caches.iter().map(|c| c.cache.clone()).collect()
+        };
+        for cache in caches {
+            if let Some((_, share)) = &cache.share {
+                cache.inner.trim(share).await;
+            }
+        }
+    }
+
+    /// Rebalances the budget and trims the caches every `period`.
+    ///
+    /// The returned future never completes and is meant to be spawned on the runtime.
+    pub async fn run(&self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            self.rebalance();
+            self.trim().await;
+        }
+    }
+
+    /// Adds a cache to the registry, sharing the budget with a given priority if any, and
+    /// rebalances the budget.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        share: Option<(u32, Arc<Share>)>,
+        cache: Arc<dyn Inspect>,
+    ) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let stats = cache.stats();
+        self.caches.lock().unwrap().push(Registered {
+            id,
+            cache: RegisteredCache {
+                share,
+                inner: cache,
+            },
+            last: (stats.hits, stats.misses),
+        });
+        self.rebalance();
+        Registration {
+            registry: Arc::downgrade(self),
+            id,
+        }
+    }
+}
+
+/// Scales the weight of an entry by `budget / share`, so that a cache whose max capacity is
+/// the whole budget holds about `share` of unscaled weight.
+pub(crate) fn scale(weight: u32, budget: u64, share: u64) -> u32 {
+    let scaled = weight as u128 * budget as u128 / share.max(1) as u128;
+    u32::try_from(scaled).unwrap_or(u32::MAX)
+}
@@ -0,0 +1,30 @@
+use sqlx::{ColumnIndex, Database, Decode, Encode, Executor, IntoArguments, Pool, Type};
+
+use crate::future::builder::{QueryBuilder, split_table};
+
+/// Returns the names of the columns of a table, in order.
+///
+/// The columns are read with the [`QueryBuilder::TABLE_COLUMNS`] query of the dialect of the
+/// pool, i.e. from `information_schema.columns` on PostgreSQL and MySQL, and from
+/// `pragma_table_info` on SQLite. An empty list is returned if the table does not exist.
+///
+/// # Arguments
+/// * `pool` - The `sqlx` database connection pool.
+/// * `table` - The name of the table, optionally qualified by its schema (`schema.table`).
+///   Unqualified tables are looked up in the current schema.
+pub async fn table_columns<DB>(pool: &Pool<DB>, table: &str) -> Result<Vec<String>, sqlx::Error>
+where
+    DB: QueryBuilder + Database,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+    String: Type<DB> + for<'q> Encode<'q, DB> + for<'r> Decode<'r, DB>,
+    Option<String>: Type<DB> + for<'q> Encode<'q, DB>,
+    usize: ColumnIndex<DB::Row>,
+{
+    let (schema, table) = split_table(table);
+    sqlx::query_scalar(DB::dialect(pool).table_columns())
+        .bind(schema.map(str::to_owned))
+        .bind(table.to_owned())
+        .fetch_all(pool)
+        .await
+}
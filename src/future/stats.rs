@@ -0,0 +1,79 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use moka::future::Cache;
+use send_sync_static::SSS;
+
+/// A point-in-time snapshot of the statistics of a `RowCache`.
+///
+/// The hit and miss counters only cover lookups made through `RowCache::try_get` and
+/// `RowCache::try_get_by_ref`. Calls to the underlying `moka::future::Cache` (reached
+/// through `Deref`) are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of lookups answered from the cache, including cached `None`s.
+    pub hits: u64,
+    /// The number of lookups that had to query the database.
+    pub misses: u64,
+    /// The number of lookups answered `None` by the filter set with
+    /// `RowCacheBuilder::existence_filter`, which are neither hits nor misses.
+    pub filtered: u64,
+    /// The approximate number of entries in the cache.
+    pub entry_count: u64,
+    /// The approximate total weighted size of the entries in the cache.
+    pub weighted_size: u64,
+}
+
+impl CacheStats {
+    /// Returns the number of lookups, i.e. `hits + misses`.
+    pub fn lookups(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Returns the ratio of lookups answered from the cache, or `0.0` if there was no lookup.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// The lookup counters shared by the methods of a `RowCache`.
+#[derive(Default)]
+pub(crate) struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    filtered: AtomicU64,
+}
+
+impl Counters {
+    /// Records the outcome of a lookup. A lookup is a miss if it computed a fresh value.
+    pub(crate) fn record(&self, fresh: bool) {
+        let counter = if fresh { &self.misses } else { &self.hits };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a lookup answered by the existence filter.
+    pub(crate) fn record_filtered(&self) {
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the statistics of the cache the lookups were made on.
+    pub(crate) fn snapshot<K, W, S>(&self, cache: &Cache<K, Option<W>, S>) -> CacheStats
+    where
+        K: Hash + Eq + SSS,
+        W: Clone + SSS,
+        S: BuildHasher + Clone + SSS,
+    {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            entry_count: cache.entry_count(),
+            weighted_size: cache.weighted_size(),
+        }
+    }
+}
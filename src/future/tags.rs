@@ -0,0 +1,77 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+/// Computes the tags of a cached value.
+pub(crate) type TagsOf<W> = Box<dyn Fn(&W) -> Vec<Box<str>> + Send + Sync>;
+
+/// The keys indexed under each tag, with the number of times they were indexed minus the
+/// number of times they were unindexed.
+type Index<K> = HashMap<Box<str>, HashMap<Arc<K>, usize>>;
+
+/// An index of the keys of a `RowCache` by the tags of their values.
+///
+/// Every insertion of a value counts its key once under each of its tags, and every removal
+/// uncounts it, so that a key replaced by a value with the same tags stays indexed whatever
+/// the order of the insertion and of the removal notification of the old value. Keys are
+/// dropped from a tag when their count reaches zero. Removals of keys that are not indexed,
+/// e.g. values inserted into the underlying cache directly, are ignored, so that they cannot
+/// cancel a later insertion. A removal notified before its insertion therefore leaves the key
+/// indexed, which at worst invalidates it needlessly.
+pub(crate) struct Tags<K, W> {
+    tags_of: TagsOf<W>,
+    index: Mutex<Index<K>>,
+}
+
+impl<K: Hash + Eq, W> Tags<K, W> {
+    pub(crate) fn new(tags_of: TagsOf<W>) -> Self {
+        Tags {
+            tags_of,
+            index: Mutex::default(),
+        }
+    }
+
+    /// Indexes a key under the tags of its newly cached value.
+    pub(crate) fn insert(&self, key: &Arc<K>, value: &Option<W>) {
+        let Some(value) = value else { return };
+        let mut index = self.index.lock().unwrap();
+        for tag in (self.tags_of)(value) {
+            let keys = index.entry(tag).or_default();
+            *keys.entry(Arc::clone(key)).or_default() += 1;
+        }
+    }
+
+    /// Unindexes a key from the tags of its removed value.
+    pub(crate) fn remove(&self, key: &Arc<K>, value: &Option<W>) {
+        let Some(value) = value else { return };
+        let mut index = self.index.lock().unwrap();
+        for tag in (self.tags_of)(value) {
+            let Entry::Occupied(mut keys) = index.entry(tag) else {
+                continue;
+            };
+            let Some(count) = keys.get_mut().get_mut(key.as_ref()) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                keys.get_mut().remove(key.as_ref());
+                if keys.get().is_empty() {
+                    keys.remove();
+                }
+            }
+        }
+    }
+
+    /// Returns the keys indexed under a tag.
+    pub(crate) fn keys(&self, tag: &str) -> Vec<Arc<K>> {
+        let index = self.index.lock().unwrap();
+        index
+            .get(tag)
+            .into_iter()
+            .flat_map(|keys| keys.keys())
+            .map(Arc::clone)
+            .collect()
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, RwLock},
+};
+
+use send_sync_static::SSS;
+use sqlx::{Database, Encode, Executor, FromRow, IntoArguments, Type};
+
+use crate::future::{
+    builder::RowCacheBuilder, cache::RowCache, error::LoadError, stats::CacheStats,
+};
+
+type TenantOf<K, T> = Box<dyn Fn(&K) -> T + Send + Sync>;
+type MakePartition<DB, K, V, W, T> = Box<dyn Fn(&T, u64) -> RowCache<DB, K, V, W> + Send + Sync>;
+type Partitions<DB, T, K, V, W> = RwLock<HashMap<T, Arc<RowCache<DB, K, V, W>>>>;
+
+/// A tenant-aware cache that partitions its capacity by a tenant extracted from the key.
+///
+/// Every tenant owns a dedicated `RowCache`, created lazily on the first lookup of one of its
+/// keys, so a noisy tenant can only evict its own rows. The weighted size of each partition is
+/// bounded by the max weight configured for the tenant, and a partition can be cleared with
+/// [`TenantCache::invalidate_tenant`] without scanning the other partitions.
+///
+/// Use `TenantCacheBuilder` to construct `TenantCache` instances.
+///
+/// # Type Parameters
+/// * `DB`, `K`, `V`, `W`: See [`RowCache`].
+/// * `T`: The type of the tenant extracted from the keys.
+pub struct TenantCache<DB: Database, T, K, V, W = Arc<V>> {
+    tenants: Partitions<DB, T, K, V, W>,
+    tenant_of: TenantOf<K, T>,
+    make_partition: MakePartition<DB, K, V, W, T>,
+    max_weight: u64,
+    max_weights: HashMap<T, u64>,
+}
+
+/// A builder for creating and configuring a `TenantCache`.
+pub struct TenantCacheBuilder<DB: Database, T, K, V, W = Arc<V>> {
+    tenant_of: TenantOf<K, T>,
+    make_partition: MakePartition<DB, K, V, W, T>,
+    max_weight: u64,
+    max_weights: HashMap<T, u64>,
+}
+
+impl<DB, T, K, V, W> TenantCacheBuilder<DB, T, K, V, W>
+where
+    DB: Database,
+    T: Clone + Hash + Eq + SSS,
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Creates a new `TenantCacheBuilder`.
+    ///
+    /// # Arguments
+    /// * `max_weight` - The default maximum weighted size of each tenant's partition.
+    ///   Without a custom weigher, this is the maximum number of entries per tenant.
+    /// * `tenant_of` - Extracts the tenant from a key.
+    /// * `make_builder` - Creates the `RowCacheBuilder` of a tenant's partition. Its max
+    ///   capacity is overwritten by the max weight of the tenant.
+    pub fn new<B>(
+        max_weight: u64,
+        tenant_of: impl Fn(&K) -> T + Send + Sync + 'static,
+        make_builder: impl Fn(&T) -> RowCacheBuilder<DB, K, V, W, B> + Send + Sync + 'static,
+    ) -> Self
+    where
+        B: Type<DB> + for<'q> Encode<'q, DB> + SSS,
+    {
+        let make_partition =
+            move |tenant: &T, max_weight| make_builder(tenant).max_capacity(max_weight).build();
+        TenantCacheBuilder {
+            tenant_of: Box::new(tenant_of),
+            make_partition: Box::new(make_partition),
+            max_weight,
+            max_weights: HashMap::new(),
+        }
+    }
+
+    /// Sets the maximum weighted size of the partition of a specific tenant,
+    /// overriding the default max weight.
+    ///
+    /// # Arguments
+    /// * `tenant` - The tenant whose quota is set.
+    /// * `max_weight` - The maximum weighted size of the tenant's partition.
+    pub fn max_weight_for(self, tenant: T, max_weight: u64) -> Self {
+        let mut builder = self;
+        builder.max_weights.insert(tenant, max_weight);
+        builder
+    }
+
+    /// Builds the `TenantCache` instance.
+    pub fn build(self) -> TenantCache<DB, T, K, V, W> {
+        TenantCache {
+            tenants: RwLock::default(),
+            tenant_of: self.tenant_of,
+            make_partition: self.make_partition,
+            max_weight: self.max_weight,
+            max_weights: self.max_weights,
+        }
+    }
+}
+
+impl<DB, T, K, V, W> TenantCache<DB, T, K, V, W>
+where
+    DB: Database,
+    T: Clone + Hash + Eq + SSS,
+    K: Clone + Hash + Eq + SSS,
+    V: Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Returns the tenant a key belongs to.
+    pub fn tenant_of(&self, key: &K) -> T {
+        (self.tenant_of)(key)
+    }
+
+    /// Returns the maximum weighted size of the partition of a tenant.
+    pub fn max_weight(&self, tenant: &T) -> u64 {
+        self.max_weights
+            .get(tenant)
+            .copied()
+            .unwrap_or(self.max_weight)
+    }
+
+    /// Returns the partition of a tenant, or `None` if the tenant has no partition.
+    pub fn partition(&self, tenant: &T) -> Option<Arc<RowCache<DB, K, V, W>>> {
+        self.tenants.read().unwrap().get(tenant).cloned()
+    }
+
+    /// Returns the partition of a tenant, creating it if it does not exist yet.
+    fn partition_or_create(&self, tenant: &T) -> Arc<RowCache<DB, K, V, W>> {
+        if let Some(partition) = self.partition(tenant) {
+            return partition;
+        }
+        self.tenants
+            .write()
+            .unwrap()
+            .entry(tenant.clone())
+            .or_insert_with(|| Arc::new((self.make_partition)(tenant, self.max_weight(tenant))))
+            .clone()
+    }
+
+    /// Returns the tenants that currently have a partition.
+    pub fn tenants(&self) -> Vec<T> {
+        self.tenants.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Discards the cached entry of a key, if any.
+    pub async fn invalidate(&self, key: &K) {
+        let partition = self
+            .tenants
+            .read()
+            .unwrap()
+            .get(&self.tenant_of(key))
+            .cloned();
+        if let Some(partition) = partition {
+            partition.invalidate(key).await;
+        }
+    }
+
+    /// Discards all the cached entries of a tenant, and removes its partition.
+    ///
+    /// Only the partition of the tenant is affected, and no closure-based invalidation
+    /// (`support_invalidation_closures`) is needed. The partition is created again by the
+    /// next lookup of one of the keys of the tenant.
+    pub fn invalidate_tenant(&self, tenant: &T) {
+        if let Some(partition) = self.tenants.write().unwrap().remove(tenant) {
+            partition.invalidate_all();
+        }
+    }
+
+    /// Returns the statistics of a tenant, or `None` if the tenant has no partition.
+    pub fn tenant_stats(&self, tenant: &T) -> Option<CacheStats> {
+        self.tenants.read().unwrap().get(tenant).map(|p| p.stats())
+    }
+
+    /// Returns the statistics of every tenant that has a partition.
+    pub fn stats(&self) -> Vec<(T, CacheStats)> {
+        self.tenants
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(tenant, partition)| (tenant.clone(), partition.stats()))
+            .collect()
+    }
+}
+
+impl<DB, T, K, V, W> TenantCache<DB, T, K, V, W>
+where
+    DB: Database,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    T: Clone + Hash + Eq + SSS,
+    K: Hash + Eq + Clone + SSS,
+    V: for<'r> FromRow<'r, DB::Row> + Unpin + SSS,
+    W: Clone + SSS,
+{
+    /// Attempts to retrieve a value from the partition of the key's tenant.
+    ///
+    /// See [`RowCache::try_get`].
+    pub async fn try_get(&self, key: K) -> Result<Option<W>, Arc<LoadError>> {
+        let partition = self.partition_or_create(&self.tenant_of(&key));
+        partition.try_get(key).await
+    }
+}
@@ -1,12 +1,57 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    convert::Infallible,
+    sync::{
+        Arc, Mutex, OnceLock, Weak,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
-use crate::future::{SqliteCache, SqliteCacheBuilder};
-use sqlx::{Pool, Sqlite, prelude::FromRow};
-use tokio::time::sleep;
+use crate::future::{
+    AdminService, AnyCache, AnyCacheBuilder, BuildError, BusTransport, CacheEvent, CacheRegistry,
+    ChangeOp, ChangedRow, DebeziumEvent, DeepSizeOf, EntryState, EventCause, InvalidationBus,
+    InvalidationLog, LoadError, LoadPriority, LocalTransport, PgCache, PgCacheBuilder,
+    PgChangeFeed, PgOutputError, PgOutputMessage, Presence, QueryBuilder, SqliteCache,
+    SqliteCacheBuilder, TcpTransport, TenantCacheBuilder, UpdateHook,
+    advisory::AdvisoryLock,
+    changes::{Change, Changes, MAX_CHANGES},
+    tags::Tags,
+};
+use http::{Method, Request, StatusCode};
+use http_body_util::BodyExt;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use moka::notification::RemovalCause;
+use serde_json::{Value, json};
+use sqlx::{
+    Any, MySql, PgPool, Pool, Postgres, Sqlite, SqliteConnection, any::AnyPoolOptions,
+    prelude::FromRow, sqlite::SqlitePoolOptions,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::sleep,
+};
+use tracing_subscriber::{
+    Layer,
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+async fn request(admin: &AdminService, method: Method, uri: &str) -> Result<(StatusCode, Value)> {
+    let request = Request::builder().method(method).uri(uri).body(())?;
+    let response = tower_service::Service::call(&mut admin.clone(), request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    let value = match body.is_empty() {
+        true => Value::Null,
+        false => serde_json::from_slice(&body)?,
+    };
+    Ok((status, value))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, DeepSizeOf, serde::Deserialize)]
 struct Cake {
     id: i64,
     name: String,
@@ -29,6 +74,34 @@ impl PartialEq<Arc<Self>> for Cake {
     }
 }
 
+/// Creates an in-memory database with a `cakes` table holding `Cake::new(id)` for each id.
+async fn bakery(ids: impl IntoIterator<Item = i64>) -> Result<(Pool<Sqlite>, Vec<Cake>)> {
+    // setting up the database
+    let url = "sqlite::memory:";
+    let pool = Pool::<Sqlite>::connect(url).await?;
+    sqlx::query(
+        "CREATE TABLE cakes (
+            id INTEGER PRIMARY KEY,
+            name VARCHAR(32),
+            fruit_id BIGINT
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // setting up the dataset
+    let cakes: Vec<Cake> = ids.into_iter().map(Cake::new).collect();
+    for cake in cakes.iter().cloned() {
+        sqlx::query("INSERT INTO cakes(id, name, fruit_id) VALUES (?, ?, ?)")
+            .bind(cake.id)
+            .bind(cake.name)
+            .bind(cake.fruit_id)
+            .execute(&pool)
+            .await?;
+    }
+    Ok((pool, cakes))
+}
+
 #[tokio::test]
 async fn it_works() -> Result<()> {
     // setting up the database
@@ -98,3 +171,1597 @@ async fn it_works() -> Result<()> {
     assert!(cache.get(&1).await.is_none());
     Ok(())
 }
+
+#[tokio::test]
+async fn tenants_are_partitioned() -> Result<()> {
+    let (pool, _) = bakery((0..3).chain(100..103)).await?;
+    let cache = TenantCacheBuilder::new(
+        512,
+        |id: &i64| id / 100,
+        move |_| SqliteCacheBuilder::<i64, Cake>::new(0, pool.clone(), "cakes"),
+    )
+    .max_weight_for(1, 1)
+    .build();
+
+    // every tenant gets its own partition
+    for id in (0..3).chain(100..103) {
+        assert_eq!(cache.try_get(id).await?, Some(Arc::new(Cake::new(id))));
+    }
+    cache.try_get(0).await?;
+    let mut tenants = cache.tenants();
+    tenants.sort();
+    assert_eq!(tenants, [0, 1]);
+
+    // per-tenant stats
+    let stats = cache.tenant_stats(&0).expect("tenant 0 is missing.");
+    assert_eq!((stats.hits, stats.misses), (1, 3));
+    let stats = cache.tenant_stats(&1).expect("tenant 1 is missing.");
+    assert_eq!((stats.hits, stats.misses), (0, 3));
+    assert_eq!(cache.tenant_stats(&2), None);
+
+    // per-tenant quotas
+    assert_eq!(cache.max_weight(&0), 512);
+    assert_eq!(cache.max_weight(&1), 1);
+    let partition = |tenant| cache.partition(&tenant).expect("partition is missing.");
+    partition(0).run_pending_tasks().await;
+    partition(1).run_pending_tasks().await;
+    assert_eq!(partition(0).entry_count(), 3);
+    assert_eq!(partition(1).entry_count(), 1);
+
+    // looking up a partition does not create it
+    assert!(cache.partition(&2).is_none());
+    assert_eq!(cache.tenants().len(), 2);
+
+    // invalidating a tenant removes its partition and does not affect the others
+    let removed = partition(0);
+    cache.invalidate_tenant(&0);
+    assert_eq!(removed.get(&0).await, None);
+    assert!(cache.partition(&0).is_none());
+    assert_eq!(cache.tenants(), [1]);
+    assert!(partition(1).iter().count() > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn columns_are_validated() -> Result<()> {
+    let (pool, _) = bakery(0..3).await?;
+    sqlx::query("ALTER TABLE cakes ADD COLUMN photo BLOB")
+        .execute(&pool)
+        .await?;
+
+    // explicit column lists are validated and used by the query
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(512, pool.clone(), "cakes")
+        .columns(["id", "name", "fruit_id"])
+        .try_build()
+        .await?;
+    assert_eq!(cache.try_get(1).await?, Some(Arc::new(Cake::new(1))));
+    assert_eq!(cache.stats().misses, 1);
+
+    // missing tables and columns are reported at build time
+    let result = SqliteCacheBuilder::<i64, Cake>::new(512, pool.clone(), "pies")
+        .try_build()
+        .await;
+    assert!(matches!(result, Err(BuildError::TableNotFound(table)) if &*table == "pies"));
+    let result = SqliteCacheBuilder::<i64, Cake>::for_table(512, pool.clone(), "cakes", "uuid")
+        .try_build()
+        .await;
+    assert!(matches!(result, Err(BuildError::ColumnNotFound { column, .. }) if &*column == "uuid"));
+    let result = SqliteCacheBuilder::<i64, Cake>::new(512, pool.clone(), "cakes")
+        .columns(["id", "flavor"])
+        .try_build()
+        .await;
+    assert!(
+        matches!(result, Err(BuildError::ColumnNotFound { column, .. }) if &*column == "flavor")
+    );
+
+    // custom queries are prepared at build time
+    let result =
+        SqliteCacheBuilder::<i64, Cake>::for_query(512, pool, "SELECT * FROM pies WHERE id = ?")
+            .try_build()
+            .await;
+    assert!(matches!(result, Err(BuildError::Database(_))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn identifiers_are_escaped() -> Result<()> {
+    // quoting
+    assert_eq!(Postgres::quote_table("public.users"), r#""public"."users""#);
+    assert_eq!(Sqlite::quote(r#"we"ird"#), r#""we""ird""#);
+    assert_eq!(MySql::quote_table("shop.we`ird"), "`shop`.`we``ird`");
+
+    // schema-qualified tables
+    let (pool, _) = bakery(0..3).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(512, pool.clone(), "main.cakes")
+        .try_build()
+        .await?;
+    assert_eq!(cache.try_get(1).await?, Some(Arc::new(Cake::new(1))));
+    let result = SqliteCacheBuilder::<i64, Cake>::new(512, pool.clone(), "temp.cakes")
+        .try_build()
+        .await;
+    assert!(matches!(result, Err(BuildError::TableNotFound(_))));
+
+    // identifiers containing quotes
+    sqlx::query(r#"CREATE TABLE "we""ird" AS SELECT * FROM cakes"#)
+        .execute(&pool)
+        .await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(512, pool, r#"we"ird"#)
+        .try_build()
+        .await?;
+    assert_eq!(cache.try_get(2).await?, Some(Arc::new(Cake::new(2))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn filters_are_bound() -> Result<()> {
+    let (pool, _) = bakery(0..4).await?;
+    sqlx::query("UPDATE cakes SET fruit_id = NULL WHERE id = 2")
+        .execute(&pool)
+        .await?;
+    sqlx::query("UPDATE cakes SET name = 'lemon drizzle' WHERE id = 3")
+        .execute(&pool)
+        .await?;
+
+    // predicates are appended to the query
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(512, pool, "cakes")
+        .filter_not_null("fruit_id")
+        .filter_eq("name", "berry delight".to_owned())
+        .filter_ne("fruit_id", 7_i64)
+        .try_build()
+        .await?;
+    assert_eq!(cache.try_get(1).await?, Some(Arc::new(Cake::new(1))));
+    assert_eq!(cache.try_get(2).await?, None);
+    assert_eq!(cache.try_get(3).await?, None);
+
+    // placeholders are numbered on PostgreSQL
+    let query =
+        PgCacheBuilder::<i64, Cake>::new(512, PgPool::connect_lazy("postgres://")?, "cakes")
+            .filter_null("deleted_at")
+            .filter_eq("tenant", "x".to_owned())
+            .filter_eq("shop", 1_i64)
+            .build()
+            .query;
+    assert_eq!(
+        &*query,
+        r#"SELECT * FROM "cakes" WHERE "id" = $1 AND "deleted_at" IS NULL AND "tenant" = $2 AND "shop" = $3"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn any_driver_works() -> Result<()> {
+    sqlx::any::install_default_drivers();
+
+    // the dialect is resolved from the URL of the pool
+    let pool = AnyPoolOptions::new().connect_lazy("postgres://localhost/bakery")?;
+    assert_eq!(Any::dialect(&pool).placeholder(2), "$2");
+    let pool = AnyPoolOptions::new().connect_lazy("mysql://localhost/bakery")?;
+    assert_eq!(
+        Any::dialect(&pool).quote_table("bakery.cakes"),
+        "`bakery`.`cakes`"
+    );
+
+    // every connection to `sqlite::memory:` opens a distinct database through `Any`
+    let pool = AnyPoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await?;
+    sqlx::query("CREATE TABLE cakes (id INTEGER PRIMARY KEY, name TEXT, fruit_id BIGINT)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO cakes VALUES (1, 'berry delight', 42), (2, 'berry delight', NULL)")
+        .execute(&pool)
+        .await?;
+    let cache: AnyCache<i64, Cake> = AnyCacheBuilder::new(512, pool, "cakes")
+        .filter_not_null("fruit_id")
+        .try_build()
+        .await?;
+    assert_eq!(cache.try_get(1).await?, Some(Arc::new(Cake::new(1))));
+    assert_eq!(cache.try_get(2).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn keys_are_mapped() -> Result<()> {
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct CakeId(i64);
+
+    let (pool, _) = bakery(0..3).await?;
+
+    // newtype keys bind their inner value
+    let cache: SqliteCache<CakeId, Cake> = SqliteCacheBuilder::new(512, pool.clone(), "cakes")
+        .map_key(|id: &CakeId| id.0)
+        .try_build()
+        .await?;
+    assert_eq!(
+        cache.try_get(CakeId(1)).await?,
+        Some(Arc::new(Cake::new(1)))
+    );
+    assert_eq!(cache.try_get(CakeId(7)).await?, None);
+    assert_eq!(
+        cache.get(&CakeId(1)).await,
+        Some(Some(Arc::new(Cake::new(1))))
+    );
+
+    // keys can be normalized before being bound
+    let cache: SqliteCache<String, Cake> =
+        SqliteCacheBuilder::for_table(512, pool.clone(), "cakes", "name")
+            .map_key(|name: &String| name.to_lowercase())
+            .build();
+    assert!(cache.try_get("Berry Delight".into()).await?.is_some());
+
+    // mapped keys cannot be filtered, as the scanned keys are not the looked up keys
+    let builder = || {
+        SqliteCacheBuilder::<String, Cake>::for_table(512, pool.clone(), "cakes", "name")
+            .map_key(|name: &String| name.to_lowercase())
+            .existence_filter(0.01)
+    };
+    let result = builder().try_build().await;
+    assert!(matches!(result, Err(BuildError::MappedKeyFilter)));
+    let built = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| builder().build()));
+    assert!(built.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn rows_are_mapped() -> Result<()> {
+    let (pool, _) = bakery(0..3).await?;
+    let cache: SqliteCache<i64, Cake, Arc<str>> =
+        SqliteCacheBuilder::<i64, String, Arc<str>>::new(512, pool, "cakes")
+            .weigher(|_, label| label.as_ref().map_or(1, |label| label.len() as u32))
+            .map_row(|cake: Cake| match cake.id {
+                2 => Err("cake 2 is a lie"),
+                id => Ok(format!("{} #{}", cake.name, id)),
+            })
+            .build();
+
+    // rows are converted before being cached
+    assert_eq!(cache.try_get(1).await?.as_deref(), Some("berry delight #1"));
+    assert_eq!(cache.try_get(7).await?, None);
+
+    // the weigher sees the converted values
+    cache.run_pending_tasks().await;
+    assert_eq!(cache.weighted_size(), "berry delight #1".len() as u64 + 1);
+
+    // failed conversions surface as errors and are not cached
+    let err = cache.try_get(2).await.expect_err("cake 2 is cached.");
+    assert!(matches!(*err, LoadError::Database(sqlx::Error::Decode(_))));
+    assert_eq!(cache.get(&2).await, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn entries_are_weighed_by_memory() -> Result<()> {
+    let (pool, _) = bakery(0..8).await?;
+    let entry_size = 0_i64.deep_size_of() + Some(Arc::new(Cake::new(0))).deep_size_of();
+    let cache: SqliteCache<i64, Cake> =
+        SqliteCacheBuilder::new(4 * entry_size as u64, pool, "cakes")
+            .weigh_by_memory()
+            .build();
+
+    // the capacity is a budget in bytes
+    cache.try_get(0).await?;
+    cache.run_pending_tasks().await;
+    assert_eq!(cache.weighted_size(), entry_size as u64);
+    for id in 1..8 {
+        cache.try_get(id).await?;
+    }
+    cache.run_pending_tasks().await;
+    assert!(cache.weighted_size() <= 4 * entry_size as u64);
+    Ok(())
+}
+
+#[tokio::test]
+async fn caches_share_a_budget() -> Result<()> {
+    let (pool, _) = bakery(0..4).await?;
+    let registry = CacheRegistry::new(1000);
+    let hot: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1, pool.clone(), "cakes")
+        .name("hot")
+        .share_budget(&registry, 1)
+        .build();
+    let cold: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1, pool.clone(), "cakes")
+        .name("cold")
+        .share_budget(&registry, 1)
+        .build();
+
+    // the budget is split by priority
+    let capacities = |registry: &CacheRegistry| {
+        let caches = registry.caches();
+        caches.iter().flat_map(|c| c.capacity).collect::<Vec<_>>()
+    };
+    assert_eq!(capacities(&registry), [500, 500]);
+
+    // the cache with the best hit ratio gets more capacity
+    for _ in 0..4 {
+        hot.try_get(0).await?;
+    }
+    cold.try_get(1).await?;
+    registry.rebalance();
+    assert_eq!(registry.caches()[0].name.as_deref(), Some("hot"));
+    assert_eq!(registry.caches()[0].stats.hits, 3);
+    // every cache gets a tenth of an even split, and the rest is split by score
+    assert_eq!(
+        capacities(&registry),
+        [50 + 900 * 175 / 275, 50 + 900 * 100 / 275]
+    );
+
+    // the own max capacity is ignored
+    for id in 0..4 {
+        cold.try_get(id).await?;
+    }
+    cold.run_pending_tasks().await;
+    assert_eq!(cold.entry_count(), 4);
+
+    // dropped caches leave the registry
+    drop(hot);
+    assert_eq!(capacities(&registry), [1000]);
+
+    // caches without priority split the budget evenly, and are trimmed to their share
+    let registry = CacheRegistry::new(4);
+    let first: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1, pool.clone(), "cakes")
+        .share_budget(&registry, 0)
+        .build();
+    for id in 0..4 {
+        first.try_get(id).await?;
+    }
+    first.run_pending_tasks().await;
+    assert_eq!(registry.caches()[0].usage, Some(4));
+    let _second: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1, pool.clone(), "cakes")
+        .share_budget(&registry, 0)
+        .build();
+    assert_eq!(capacities(&registry), [2, 2]);
+    registry.trim().await;
+    first.run_pending_tasks().await;
+    assert_eq!(registry.caches()[0].usage, Some(2));
+    assert_eq!(first.entry_count(), 2);
+
+    // caches without priority next to caches with a priority keep a share
+    let registry = CacheRegistry::new(1000);
+    let low: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1, pool.clone(), "cakes")
+        .share_budget(&registry, 0)
+        .build();
+    let _high: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1, pool, "cakes")
+        .share_budget(&registry, 3)
+        .build();
+    assert_eq!(capacities(&registry), [50, 950]);
+    for id in 0..4 {
+        low.try_get(id).await?;
+    }
+    low.run_pending_tasks().await;
+    assert_eq!(low.entry_count(), 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_service_inspects_caches() -> Result<()> {
+    let (pool, _) = bakery(0..2).await?;
+    let registry = CacheRegistry::new(100);
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(100, pool, "cakes")
+        .name("cakes")
+        .time_to_live(Duration::from_secs(300))
+        .time_to_live_for_none(Duration::from_secs(30))
+        .key_parser(|key| key.parse().ok())
+        .register(&registry)
+        .build();
+    cache.try_get(0).await?;
+    cache.try_get(-1).await?;
+
+    // the remaining time before expiry is tracked
+    let expires_in = |state| match state {
+        EntryState::Found { expires_in, .. } | EntryState::NotFound { expires_in } => expires_in,
+        EntryState::Absent => None,
+    };
+    let found = expires_in(cache.inspect(&0).await).unwrap();
+    assert!(found > Duration::from_secs(299) && found <= Duration::from_secs(300));
+    let not_found = expires_in(cache.inspect(&-1).await).unwrap();
+    assert!(not_found > Duration::from_secs(29) && not_found <= Duration::from_secs(30));
+    assert_eq!(cache.inspect(&1).await, EntryState::Absent);
+
+    // caches are listed with their stats
+    let admin = AdminService::new(registry);
+    let (status, caches) = request(&admin, Method::GET, "/").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(caches[0]["name"], "cakes");
+    assert_eq!(caches[0]["stats"]["misses"], 2);
+    assert_eq!(caches[0]["capacity"], Value::Null);
+
+    // keys are looked up by their cached state
+    let (_, found) = request(&admin, Method::GET, "/cakes/0").await?;
+    assert_eq!(found["state"], "found");
+    assert!(found["expires_in_ms"].as_u64().unwrap() > 299_000);
+    let (_, not_found) = request(&admin, Method::GET, "/cakes/-1").await?;
+    assert_eq!(not_found["state"], "not_found");
+    let (_, absent) = request(&admin, Method::GET, "/cakes/1").await?;
+    assert_eq!(
+        absent,
+        json!({ "key": "1", "state": "absent", "expires_in_ms": null })
+    );
+    let (status, _) = request(&admin, Method::GET, "/cakes/zero").await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = request(&admin, Method::GET, "/pies").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // keys and caches are invalidated
+    let (status, _) = request(&admin, Method::DELETE, "/cakes/0").await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(cache.inspect(&0).await, EntryState::Absent);
+    let (status, _) = request(&admin, Method::DELETE, "/cakes").await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(cache.inspect(&-1).await, EntryState::Absent);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_service_is_served_over_http() -> Result<()> {
+    let (pool, _) = bakery(0..2).await?;
+    let registry = CacheRegistry::new(100);
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(100, pool, "cakes")
+        .name("cakes")
+        .key_parser(|key| key.parse().ok())
+        .register(&registry)
+        .build();
+    cache.try_get(0).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let service = TowerToHyperService::new(AdminService::new(registry));
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let service = service.clone();
+            tokio::spawn(async move {
+                let connection = hyper::server::conn::http1::Builder::new();
+                let _ = connection
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    let get = |path: &str| {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n");
+        async move {
+            let mut stream = TcpStream::connect(addr).await?;
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            Result::Ok(response)
+        }
+    };
+    let found = get("/cakes/0").await?;
+    assert!(found.starts_with("HTTP/1.1 200 OK\r\n"), "{found}");
+    assert!(found.contains(r#""state":"found""#), "{found}");
+    let missing = get("/pies").await?;
+    assert!(
+        missing.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{missing}"
+    );
+    Ok(())
+}
+
+type Fields = Vec<(&'static str, String)>;
+
+/// A layer recording the fields of the spans, in order of creation.
+#[derive(Clone, Default)]
+struct Spans(Arc<Mutex<Vec<(&'static str, Fields)>>>);
+
+/// The index of a span in `Spans`.
+struct SpanIndex(usize);
+
+impl Spans {
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    fn name(&self, index: usize) -> &'static str {
+        self.0.lock().unwrap()[index].0
+    }
+
+    fn field(&self, index: usize, name: &str) -> Option<String> {
+        let spans = self.0.lock().unwrap();
+        let fields = &spans[index].1;
+        fields
+            .iter()
+            .rfind(|(field, _)| *field == name)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+struct Visitor<'a>(&'a mut Fields);
+
+impl tracing::field::Visit for Visitor<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.push((field.name(), format!("{value:?}")));
+    }
+}
+
+impl<S> Layer<S> for Spans
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        let mut fields = Vec::new();
+        attrs.record(&mut Visitor(&mut fields));
+        let mut spans = self.0.lock().unwrap();
+        spans.push((attrs.metadata().name(), fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanIndex(spans.len() - 1));
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        let Some(span) = ctx.span(id) else { return };
+        if let Some(SpanIndex(index)) = span.extensions().get::<SpanIndex>() {
+            values.record(&mut Visitor(&mut self.0.lock().unwrap()[*index].1));
+        }
+    }
+}
+
+#[tokio::test]
+async fn lookups_are_traced() -> Result<()> {
+    let (pool, _) = bakery(0..1).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes")
+        .name("cakes")
+        .build();
+    let spans = Spans::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+    // misses are loaded from the database
+    cache.try_get(0).await?;
+    assert_eq!(spans.name(0), "row_cache.get");
+    assert_eq!(spans.field(0, "cache").as_deref(), Some("\"cakes\""));
+    assert_eq!(spans.field(0, "outcome").as_deref(), Some("\"miss\""));
+    assert_eq!(spans.name(1), "row_cache.load");
+    assert_eq!(spans.field(1, "found").as_deref(), Some("true"));
+    assert!(spans.field(1, "duration_ms").is_some());
+    cache.try_get(-1).await?;
+    assert_eq!(spans.field(3, "found").as_deref(), Some("false"));
+
+    // hits are not loaded
+    cache.try_get(0).await?;
+    assert_eq!(spans.field(4, "outcome").as_deref(), Some("\"hit\""));
+    cache.try_get_by_ref(&-1).await?;
+    assert_eq!(spans.field(5, "outcome").as_deref(), Some("\"null_hit\""));
+    assert_eq!(spans.len(), 6);
+    Ok(())
+}
+
+/// Receives the next event, failing if none is published within a second.
+async fn next<T: Clone>(events: &mut tokio::sync::broadcast::Receiver<T>) -> Result<T> {
+    Ok(tokio::time::timeout(Duration::from_secs(1), events.recv()).await??)
+}
+
+#[tokio::test]
+async fn changes_are_published() -> Result<()> {
+    let (pool, _) = bakery(0..1).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes")
+        .time_to_live(Duration::from_millis(300))
+        .build();
+    let mut events = cache.subscribe();
+    let event = |key, cause, old, new| CacheEvent {
+        key: Arc::new(key),
+        cause,
+        old,
+        new,
+    };
+    use Presence::{Absent, Found, NotFound};
+
+    // loads are published, hits are not
+    cache.try_get(0).await?;
+    cache.try_get(0).await?;
+    cache.try_get(-1).await?;
+    assert_eq!(
+        next(&mut events).await?,
+        event(0, EventCause::Load, Absent, Found)
+    );
+    assert_eq!(
+        next(&mut events).await?,
+        event(-1, EventCause::Load, Absent, NotFound)
+    );
+
+    // inserts and refreshes replace the entries
+    cache.insert(0, None).await;
+    assert_eq!(
+        next(&mut events).await?,
+        event(0, EventCause::Insert, Found, NotFound)
+    );
+    cache.refresh(0).await?;
+    assert_eq!(
+        next(&mut events).await?,
+        event(0, EventCause::Refresh, NotFound, Found)
+    );
+
+    // invalidations and evictions are published on removal
+    cache.invalidate(&0).await;
+    assert_eq!(
+        next(&mut events).await?,
+        event(0, EventCause::Invalidate, Found, Absent)
+    );
+    sleep(Duration::from_millis(400)).await;
+    cache.run_pending_tasks().await;
+    let expired = EventCause::Evict(RemovalCause::Expired);
+    assert_eq!(
+        next(&mut events).await?,
+        event(-1, expired, NotFound, Absent)
+    );
+    assert!(events.try_recv().is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalidations_cascade_to_dependents() -> Result<()> {
+    let (pool, _) = bakery(0..2).await?;
+    let cakes: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes").build();
+    let slices: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes").build();
+    let menu: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes").build();
+    slices.depends_on(&cakes, |id: &i64| [*id]);
+    menu.depends_on_all(&slices);
+    for id in 0..2 {
+        cakes.try_get(id).await?;
+        slices.try_get(id).await?;
+        menu.try_get(id).await?;
+    }
+
+    // invalidations cascade to the dependent keys, transitively
+    cakes.invalidate(&0).await;
+    assert!(!slices.contains_key(&0));
+    assert!(slices.contains_key(&1));
+    assert!(!menu.contains_key(&0) && !menu.contains_key(&1));
+
+    // inserts cascade too, loads do not
+    slices.try_get(0).await?;
+    cakes.try_get(0).await?;
+    assert!(slices.contains_key(&0));
+    cakes.insert(1, None).await;
+    assert!(!slices.contains_key(&1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn entries_are_invalidated_by_tag() -> Result<()> {
+    let (pool, _) = bakery(0..4).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes")
+        .tags(|cake: &Arc<Cake>| [cake.id % 2])
+        .build();
+    for id in 0..4 {
+        cache.try_get(id).await?;
+    }
+
+    // only the tagged entries are invalidated
+    cache.invalidate_tag("0").await;
+    let cached = || {
+        (0..4)
+            .filter(|id| cache.contains_key(id))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(cached(), [1, 3]);
+
+    // tags follow the values
+    cache.insert(1, Some(Arc::new(Cake::new(2)))).await;
+    cache.invalidate_tag("1").await;
+    assert_eq!(cached(), [1]);
+    cache.invalidate_tag("0").await;
+    assert_eq!(cached().len(), 0);
+
+    // the removal of a value that was never indexed does not cancel a later insertion
+    moka::future::Cache::insert(&cache, 0, Some(Arc::new(Cake::new(0)))).await;
+    moka::future::Cache::invalidate(&cache, &0).await;
+    cache.run_pending_tasks().await;
+    cache.try_get(0).await?;
+    cache.invalidate_tag("0").await;
+    assert_eq!(cached().len(), 0);
+
+    // a removal notified before its insertion leaves the key indexed
+    let tags = Tags::new(Box::new(|tag: &&str| vec![Box::from(*tag)]));
+    let key = Arc::new(0);
+    tags.remove(&key, &Some("0"));
+    tags.insert(&key, &Some("0"));
+    assert_eq!(tags.keys("0"), [Arc::clone(&key)]);
+    tags.insert(&key, &Some("1"));
+    tags.remove(&key, &Some("0"));
+    assert_eq!(tags.keys("0").len(), 0);
+    assert_eq!(tags.keys("1"), [key]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn generations_invalidate_entries() -> Result<()> {
+    let (pool, _) = bakery(0..2).await?;
+    sqlx::query("CREATE TABLE generations (name TEXT PRIMARY KEY, generation BIGINT)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO generations VALUES ('cakes', 7)")
+        .execute(&pool)
+        .await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+        .generation_source("SELECT generation FROM generations WHERE name = 'cakes'")
+        .build();
+
+    // the first synchronization is a baseline
+    cache.try_get(0).await?;
+    assert_eq!(cache.sync_generation().await?, 0);
+    assert_eq!(cache.sync_generation().await?, 0);
+    assert!(cache.contains_key(&0));
+
+    // bumping the source makes the entries stale
+    sqlx::query("UPDATE generations SET generation = 8")
+        .execute(&pool)
+        .await?;
+    assert_eq!(cache.sync_generation().await?, 1);
+    assert!(!cache.contains_key(&0));
+
+    // and so does bumping the cache
+    cache.try_get(1).await?;
+    assert_eq!(cache.bump_generation(), 2);
+    assert_eq!(cache.generation(), 2);
+    assert!(!cache.contains_key(&1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn loads_outdated_by_generations_are_not_cached() -> Result<()> {
+    let (pool, _) = bakery(0..2).await?;
+    let bumper = Arc::new(OnceLock::<Weak<SqliteCache<i64, Cake>>>::new());
+    let bumps = Arc::new(AtomicUsize::new(0));
+    let (bumping, remaining) = (Arc::clone(&bumper), Arc::clone(&bumps));
+    let cache = Arc::new(
+        SqliteCacheBuilder::<i64, Cake>::new(16, pool, "cakes")
+            .map_row(move |cake: Cake| {
+                // new generations start while the rows are fetched
+                let cache = bumping.get().and_then(Weak::upgrade);
+                let bump = remaining
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                if let (Some(cache), Ok(_)) = (cache, bump) {
+                    cache.bump_generation();
+                }
+                Ok::<_, Infallible>(cake)
+            })
+            .build(),
+    );
+    let _ = bumper.set(Arc::downgrade(&cache));
+
+    // a row fetched again in the new generation is cached
+    bumps.store(1, Ordering::SeqCst);
+    assert!(cache.try_get(0).await?.is_some());
+    assert_eq!(cache.generation(), 1);
+    assert!(cache.contains_key(&0));
+
+    // a row outdated by every fetch is returned without being cached
+    bumps.store(10, Ordering::SeqCst);
+    assert!(cache.try_get(1).await?.is_some());
+    assert!(!cache.contains_key(&1));
+    assert!(cache.refresh(1).await?.is_some());
+    assert!(!cache.contains_key(&1));
+    bumps.store(0, Ordering::SeqCst);
+    assert!(cache.try_get(1).await?.is_some());
+    assert!(cache.contains_key(&1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn updates_invalidate_entries() -> Result<()> {
+    let hook = UpdateHook::default();
+    let attach = hook.clone();
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .after_connect(move |conn, _| {
+            let hook = attach.clone();
+            Box::pin(async move { hook.attach(conn).await })
+        })
+        .connect("sqlite::memory:")
+        .await?;
+    sqlx::query("CREATE TABLE cakes (id INTEGER PRIMARY KEY, name TEXT, fruit_id BIGINT)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO cakes VALUES (0, 'berry delight', 42), (1, 'carrot', NULL)")
+        .execute(&pool)
+        .await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+        .invalidate_on_update(&hook, Some)
+        .build();
+    assert_eq!(cache.try_get(0).await?, Some(Arc::new(Cake::new(0))));
+    assert_eq!(cache.try_get(1).await?.map(|cake| cake.id), Some(1));
+
+    // rolled back changes are ignored
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM cakes WHERE id = 0")
+        .execute(&mut *tx)
+        .await?;
+    tx.rollback().await?;
+    assert!(matches!(cache.inspect(&0).await, EntryState::Found { .. }));
+
+    // committed changes invalidate the changed rows only
+    sqlx::query("UPDATE cakes SET name = 'lemon' WHERE id = 0")
+        .execute(&pool)
+        .await?;
+    let cake = cache.try_get(0).await?;
+    assert_eq!(cake.map(|cake| cake.name.clone()).as_deref(), Some("lemon"));
+    assert!(matches!(cache.inspect(&1).await, EntryState::Found { .. }));
+    sqlx::query("DELETE FROM cakes WHERE id = 1")
+        .execute(&pool)
+        .await?;
+    assert_eq!(cache.try_get(1).await?, None);
+
+    // tables emptied at once report their rows too, while tables can still be dropped
+    assert!(cache.try_get(0).await?.is_some());
+    sqlx::query("DELETE FROM cakes").execute(&pool).await?;
+    assert_eq!(cache.try_get(0).await?, None);
+    for statement in ["CREATE TABLE pies (id INTEGER)", "DROP TABLE pies"] {
+        sqlx::query(statement).execute(&pool).await?;
+    }
+    sqlx::query("CREATE TABLE pies (id INTEGER)")
+        .execute(&pool)
+        .await?;
+
+    // bulk writes collapse the queued changes into the invalidation of the whole cache
+    sqlx::query(
+        "WITH RECURSIVE ids(id) AS (SELECT 100 UNION ALL SELECT id + 1 FROM ids LIMIT 2000)
+         INSERT INTO cakes (id, name) SELECT id, 'lemon' FROM ids",
+    )
+    .execute(&pool)
+    .await?;
+    assert_eq!(cache.changes.take().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn queued_changes_are_bounded() {
+    let changes = Changes::default();
+    assert!(changes.take().is_empty());
+    for key in 0..MAX_CHANGES {
+        changes.push(Change::Key(key));
+    }
+    assert_eq!(changes.take().len(), MAX_CHANGES);
+
+    // overflowing changes collapse into a single change of all the rows
+    for key in 0..=MAX_CHANGES {
+        changes.push(Change::Key(key));
+    }
+    changes.push(Change::Key(0));
+    assert!(matches!(changes.take()[..], [Change::All]));
+
+    // changes of all the rows make the other changes redundant
+    changes.push(Change::Key(0));
+    changes.push(Change::All);
+    changes.push(Change::Key(1));
+    assert!(matches!(changes.take()[..], [Change::All]));
+    assert!(changes.take().is_empty());
+}
+
+#[tokio::test]
+async fn logged_changes_invalidate_entries() -> Result<()> {
+    let (pool, _) = bakery(0..3).await?;
+    let log = InvalidationLog::new("changes").batch_size(2);
+    log.install(&pool, "cakes", "id").await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+        .key_parser(|key| key.parse().ok())
+        .invalidation_log(&log)
+        .build();
+    assert_eq!(cache.sync_log().await?, 0);
+    for id in 0..3 {
+        cache.try_get(id).await?;
+    }
+
+    // inserts, updates and deletes are logged, and read in batches
+    for statement in [
+        "UPDATE cakes SET name = 'lemon' WHERE id = 0",
+        "DELETE FROM cakes WHERE id = 1",
+        "INSERT INTO cakes (id, name) VALUES (3, 'carrot')",
+    ] {
+        sqlx::query(statement).execute(&pool).await?;
+    }
+    cache.try_get(3).await?;
+    assert_eq!(cache.sync_log().await?, 3);
+    let cached = || {
+        (0..4)
+            .filter(|id| cache.contains_key(id))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(cached(), [2]);
+    assert_eq!(cache.sync_log().await?, 0);
+
+    // the log can be pruned
+    log.prune(&pool, 0).await?;
+    let entries: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM changes")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(entries, 0);
+    cache.try_get(0).await?;
+    sqlx::query("DELETE FROM cakes WHERE id = 0")
+        .execute(&pool)
+        .await?;
+    assert_eq!(cache.sync_log().await?, 1);
+    assert_eq!(cache.try_get(0).await?, None);
+    Ok(())
+}
+
+/// Encodes a `pgoutput` message from its type and fields.
+fn pgoutput(kind: u8, fields: &[&[u8]]) -> Vec<u8> {
+    let mut message = vec![kind];
+    message.extend(fields.concat());
+    message
+}
+
+/// Encodes the values of a row of a `pgoutput` message.
+fn tuple(values: &[Option<&str>]) -> Vec<u8> {
+    let mut tuple = (values.len() as u16).to_be_bytes().to_vec();
+    for value in values {
+        match value {
+            Some(text) => {
+                tuple.push(b't');
+                tuple.extend((text.len() as u32).to_be_bytes());
+                tuple.extend(text.as_bytes());
+            }
+            None => tuple.push(b'n'),
+        }
+    }
+    tuple
+}
+
+/// Encodes the `Relation` message of the `cakes` table.
+fn cakes_relation() -> Vec<u8> {
+    let mut columns = 3_u16.to_be_bytes().to_vec();
+    for (key, name, oid) in [(1, "id", 20_u32), (0, "name", 25), (0, "fruit_id", 20)] {
+        columns.push(key);
+        columns.extend(name.as_bytes());
+        columns.push(0);
+        columns.extend(oid.to_be_bytes());
+        columns.extend((-1_i32).to_be_bytes());
+    }
+    let id = 16384_u32.to_be_bytes();
+    pgoutput(b'R', &[&id, b"public\0cakes\0d", &columns])
+}
+
+/// Builds a `Cake` from a row of a `pgoutput` message.
+fn cake_of(row: &ChangedRow<'_>) -> Option<Arc<Cake>> {
+    Some(Arc::new(Cake {
+        id: row.get("id")?.parse().ok()?,
+        name: row.get("name")?.to_owned(),
+        fruit_id: row.get("fruit_id").and_then(|id| id.parse().ok()),
+    }))
+}
+
+#[tokio::test]
+async fn pgoutput_messages_are_decoded() -> Result<()> {
+    let PgOutputMessage::Relation(relation) = PgOutputMessage::decode(&cakes_relation())? else {
+        panic!("not a relation");
+    };
+    assert_eq!(
+        (relation.id, relation.namespace.as_str()),
+        (16384, "public")
+    );
+    let columns: Vec<_> = relation
+        .columns
+        .iter()
+        .map(|c| (c.name.as_str(), c.key))
+        .collect();
+    assert_eq!(
+        columns,
+        [("id", true), ("name", false), ("fruit_id", false)]
+    );
+
+    let id = 16384_u32.to_be_bytes();
+    let old = tuple(&[Some("1"), None, None]);
+    let new = tuple(&[Some("2"), Some("lemon"), None]);
+    let update = PgOutputMessage::decode(&pgoutput(b'U', &[&id, b"K", &old, b"N", &new]))?;
+    let PgOutputMessage::Update {
+        relation,
+        old,
+        key_only,
+        new,
+    } = update
+    else {
+        panic!("not an update");
+    };
+    assert_eq!(relation, 16384);
+    assert!(key_only);
+    assert_eq!(
+        old.map(|old| old[0].as_str().map(str::to_owned)),
+        Some(Some("1".into()))
+    );
+    assert_eq!(new[1].as_str(), Some("lemon"));
+    let truncate = pgoutput(b'T', &[&1_u32.to_be_bytes(), &[0], &id]);
+    assert_eq!(
+        PgOutputMessage::decode(&truncate)?,
+        PgOutputMessage::Truncate {
+            relations: vec![16384]
+        }
+    );
+
+    // malformed messages are rejected
+    let insert = pgoutput(b'I', &[&id, b"N", &tuple(&[Some("1")])]);
+    let error = PgOutputMessage::decode(&insert[..insert.len() - 1]);
+    assert_eq!(error, Err(PgOutputError::UnexpectedEnd));
+    let error = PgOutputMessage::decode(&pgoutput(b'D', &[&id, b"N"]));
+    assert_eq!(error, Err(PgOutputError::Unexpected(b'N')));
+    Ok(())
+}
+
+#[tokio::test]
+async fn change_feed_applies_changes() -> Result<()> {
+    let pool = PgPool::connect_lazy("postgres://")?;
+    let invalidated: Arc<PgCache<i64, Cake>> =
+        Arc::new(PgCacheBuilder::new(16, pool.clone(), "cakes").build());
+    let updated: Arc<PgCache<i64, Cake>> = Arc::new(PgCacheBuilder::new(16, pool, "cakes").build());
+    for id in 1..4 {
+        invalidated.insert(id, Some(Arc::new(Cake::new(id)))).await;
+        updated.insert(id, Some(Arc::new(Cake::new(id)))).await;
+    }
+    let complete = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&complete);
+    let key = move |row: &ChangedRow<'_>| {
+        seen.lock().unwrap().push(row.is_complete());
+        row.get("id")?.parse().ok()
+    };
+    let feed = PgChangeFeed::new("slot", "caches")
+        .invalidate(&invalidated, "public.cakes", key.clone())
+        .update(&updated, "cakes", key, cake_of);
+
+    // changes of unknown tables are ignored
+    let id = 16384_u32.to_be_bytes();
+    let update = pgoutput(
+        b'U',
+        &[&id, b"N", &tuple(&[Some("1"), Some("lemon"), None])],
+    );
+    feed.apply(&update).await?;
+    assert!(invalidated.contains_key(&1));
+
+    feed.apply(&cakes_relation()).await?;
+    feed.apply(&update).await?;
+    let delete = pgoutput(b'D', &[&id, b"K", &tuple(&[Some("2"), None, None])]);
+    feed.apply(&delete).await?;
+    let cached = |cache: &PgCache<i64, Cake>| {
+        (1..4)
+            .filter(|id| cache.contains_key(id))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(cached(&invalidated), [3]);
+    assert_eq!(cached(&updated), [1, 3]);
+    let lemon = updated
+        .get(&1)
+        .await
+        .flatten()
+        .map(|cake| cake.name.clone());
+    assert_eq!(lemon.as_deref(), Some("lemon"));
+
+    // previous rows limited to their key columns are not complete, unlike whole rows
+    assert_eq!(*complete.lock().unwrap(), [true, true, false, false]);
+    let full = tuple(&[Some("3"), Some("carrot"), None]);
+    let update = pgoutput(b'U', &[&id, b"O", &full, b"N", &full]);
+    complete.lock().unwrap().clear();
+    feed.apply(&update).await?;
+    assert_eq!(*complete.lock().unwrap(), [true, true, true, true]);
+
+    // truncating the table invalidates the caches
+    feed.apply(&pgoutput(b'T', &[&1_u32.to_be_bytes(), &[0], &id]))
+        .await?;
+    assert!(cached(&updated).is_empty());
+
+    // undecodable messages invalidate all the caches
+    for id in 1..4 {
+        updated.insert(id, Some(Arc::new(Cake::new(id)))).await;
+    }
+    feed.apply_or_invalidate(&pgoutput(b'U', &[&id, b"N"]))
+        .await;
+    assert!(cached(&updated).is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn debezium_events_are_applied() -> Result<()> {
+    let (pool, _) = bakery(0..0).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes").build();
+    for id in 1..4 {
+        cache.insert(id, Some(Arc::new(Cake::new(id)))).await;
+    }
+    let apply = async |event: Value| -> Result<DebeziumEvent> {
+        let event = DebeziumEvent::from_slice(event.to_string().as_bytes())?;
+        cache.apply_change(&event.rows::<Cake>("id")).await;
+        Ok(event)
+    };
+
+    // updates replace entries in place, with or without the schema envelope
+    let lemon = json!({"id": 1, "name": "lemon", "fruit_id": null});
+    let update = apply(json!({
+        "schema": {"type": "struct"},
+        "payload": {
+            "op": "u",
+            "before": {"id": 1, "name": "berry delight", "fruit_id": 42},
+            "after": lemon,
+            "source": {"schema": "public", "table": "cakes"},
+        },
+    }))
+    .await?;
+    assert_eq!(update.op, ChangeOp::Update);
+    assert_eq!(update.table.as_deref(), Some("public.cakes"));
+    let cake = cache.get(&1).await.flatten();
+    assert_eq!(cake.map(|cake| cake.name.clone()).as_deref(), Some("lemon"));
+
+    // changed keys invalidate the previous key
+    let after = json!({"id": 4, "name": "lime", "fruit_id": null});
+    apply(json!({"op": "u", "before": {"id": 2}, "after": after})).await?;
+    assert!(!cache.contains_key(&2));
+    assert!(cache.contains_key(&4));
+
+    // rows that cannot be deserialized invalidate the entry, deletes invalidate it
+    apply(json!({"op": "c", "after": {"id": 1}})).await?;
+    assert!(!cache.contains_key(&1));
+    apply(json!({"op": "d", "before": {"id": 3}, "after": null})).await?;
+    assert!(!cache.contains_key(&3));
+
+    // truncates invalidate the whole cache
+    apply(json!({"op": "t", "source": {"db": "bakery", "table": "cakes"}})).await?;
+    assert!(!cache.contains_key(&4));
+
+    // tombstones and unknown operations are rejected
+    assert!(DebeziumEvent::from_slice(b"null").is_err());
+    assert!(DebeziumEvent::from_slice(br#"{"op": "m"}"#).is_err());
+    Ok(())
+}
+
+/// Builds a replica of the cakes cache on a bus, and spawns the bus.
+fn replica(pool: &Pool<Sqlite>, transport: impl BusTransport) -> SqliteCache<i64, Cake> {
+    let bus = InvalidationBus::new(transport);
+    tokio::spawn({
+        let bus = bus.clone();
+        async move { bus.run().await }
+    });
+    SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+        .key_parser(|key| key.parse().ok())
+        .invalidation_bus(&bus, "cakes", i64::to_string)
+        .build()
+}
+
+#[tokio::test]
+async fn applied_changes_are_not_broadcast() -> Result<()> {
+    let (pool, _) = bakery(1..2).await?;
+    let local = LocalTransport::new(16);
+    let [first, second] = [replica(&pool, local.clone()), replica(&pool, local.clone())];
+    first.try_get(1).await?;
+    second.try_get(1).await?;
+    let event = json!({"op": "u", "after": {"id": 1, "name": "lemon", "fruit_id": null}});
+    let event = DebeziumEvent::from_slice(event.to_string().as_bytes())?;
+    first.apply_change(&event.rows::<Cake>("id")).await;
+
+    // the change feed of each replica applies the changes, the bus does not evict them
+    let pool = PgPool::connect_lazy("postgres://")?;
+    let replicas: [Arc<PgCache<i64, Cake>>; 2] = std::array::from_fn(|_| {
+        let bus = InvalidationBus::new(local.clone());
+        tokio::spawn({
+            let bus = bus.clone();
+            async move { bus.run().await }
+        });
+        Arc::new(
+            PgCacheBuilder::new(16, pool.clone(), "cakes")
+                .key_parser(|key| key.parse().ok())
+                .invalidation_bus(&bus, "cakes", i64::to_string)
+                .build(),
+        )
+    });
+    for replica in &replicas {
+        moka::future::Cache::insert(replica, 2, Some(Arc::new(Cake::new(2)))).await;
+    }
+    let feed = PgChangeFeed::new("slot", "caches").update(
+        &replicas[0],
+        "cakes",
+        |row| row.get("id")?.parse().ok(),
+        cake_of,
+    );
+    let id = 16384_u32.to_be_bytes();
+    feed.apply(&cakes_relation()).await?;
+    let row = tuple(&[Some("2"), Some("lime"), None]);
+    feed.apply(&pgoutput(b'U', &[&id, b"N", &row])).await?;
+
+    sleep(Duration::from_millis(50)).await;
+    assert_ne!(second.inspect(&1).await, EntryState::Absent);
+    assert_ne!(replicas[1].inspect(&2).await, EntryState::Absent);
+    Ok(())
+}
+
+/// Waits until the entry of a key is invalidated.
+async fn invalidated(cache: &SqliteCache<i64, Cake>, key: i64) -> bool {
+    for _ in 0..100 {
+        if cache.inspect(&key).await == EntryState::Absent {
+            return true;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn bus_invalidates_replicas() -> Result<()> {
+    let (pool, _) = bakery(1..3).await?;
+    let local = LocalTransport::new(16);
+    let tcp = [
+        TcpTransport::bind("127.0.0.1:0").await?,
+        TcpTransport::bind("127.0.0.1:0").await?,
+    ];
+    let addrs = tcp.each_ref().map(TcpTransport::local_addr);
+    let [a, b] = tcp;
+    let replicas = [
+        [replica(&pool, local.clone()), replica(&pool, local)],
+        [
+            replica(&pool, a.peers([addrs[1]])),
+            replica(&pool, b.peers([addrs[0]])),
+        ],
+    ];
+
+    for [first, second] in &replicas {
+        // connecting to a peer does not invalidate its caches
+        for replica in [first, second] {
+            replica.try_get(1).await?;
+            replica.try_get(2).await?;
+        }
+
+        // inserts and invalidations reach the other replica, but not the sender
+        first.insert(1, Some(Arc::new(Cake::new(1)))).await;
+        assert!(invalidated(second, 1).await);
+        assert!(second.contains_key(&2));
+        second.broadcast_invalidate(&2).await;
+        assert!(invalidated(first, 2).await);
+        sleep(Duration::from_millis(50)).await;
+        assert!(first.contains_key(&1));
+    }
+
+    // a peer that missed messages is told so once it is reachable
+    let unbound = TcpTransport::bind("127.0.0.1:0").await?;
+    let addr = unbound.local_addr();
+    drop(unbound);
+    let sender = TcpTransport::bind("127.0.0.1:0").await?;
+    let sender = replica(
+        &pool,
+        sender
+            .peers([addr])
+            .connect_timeout(Duration::from_millis(10)),
+    );
+    sender.broadcast_invalidate(&1).await;
+    sleep(Duration::from_millis(50)).await;
+    let peer = replica(&pool, TcpTransport::bind(addr).await?);
+    peer.try_get(2).await?;
+    sender.broadcast_invalidate(&1).await;
+    assert!(invalidated(&peer, 2).await);
+    Ok(())
+}
+
+#[tokio::test]
+async fn expiries_are_randomized() -> Result<()> {
+    let (pool, _) = bakery(0..8).await?;
+    let ttl = Duration::from_secs(10);
+    let expires_in = |state| match state {
+        EntryState::Found { expires_in, .. } | EntryState::NotFound { expires_in } => expires_in,
+        EntryState::Absent => None,
+    };
+
+    // jittered entries expire within the spread, `None`s included
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+        .time_to_live(ttl)
+        .time_to_live_for_none(ttl)
+        .ttl_jitter(0.5)
+        .build();
+    let mut expiries = Vec::new();
+    for id in -8..8 {
+        cache.try_get(id).await?;
+        expiries.push(expires_in(cache.inspect(&id).await).unwrap());
+    }
+    assert!(
+        expiries
+            .iter()
+            .all(|&expiry| expiry <= ttl && expiry >= ttl / 2)
+    );
+    assert!(expiries.iter().any(|&expiry| expiry != expiries[0]));
+
+    // with an extreme `beta`, every hit refreshes its entry early, in the background
+    let cache: Arc<SqliteCache<i64, Cake>> = Arc::new(
+        SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+            .time_to_live(ttl)
+            .early_refresh(1e12)
+            .build(),
+    );
+    let mut events = cache.subscribe();
+    for id in [0, -1] {
+        cache.try_get(id).await?;
+        cache.try_get(id).await?;
+        assert_eq!(events.try_recv()?.cause, EventCause::Load);
+    }
+    assert!(events.try_recv().is_err());
+    let refresher = tokio::spawn({
+        let cache = Arc::clone(&cache);
+        async move { cache.run_early_refresh().await }
+    });
+    for _ in [0, -1] {
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv()).await??;
+        assert_eq!(event.cause, EventCause::Refresh);
+    }
+
+    // refreshing an unchanged value does not invalidate the entries depending on it
+    let slices: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes").build();
+    slices.depends_on(&cache, |id: &i64| [*id]);
+    slices.try_get(0).await?;
+    cache.try_get(0).await?;
+    let event = tokio::time::timeout(Duration::from_secs(1), events.recv()).await??;
+    assert_eq!(event.cause, EventCause::Refresh);
+    assert!(slices.contains_key(&0));
+    refresher.abort();
+
+    // keys drawn by the other reads are forgotten with their entry
+    let due = || {
+        cache
+            .early_refresh
+            .as_ref()
+            .unwrap()
+            .due
+            .lock()
+            .unwrap()
+            .len()
+    };
+    cache.get(&0).await;
+    assert_eq!(due(), 1);
+    cache.invalidate(&0).await;
+    assert_eq!(due(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_keys_back_off() -> Result<()> {
+    let (pool, _) = bakery(0..0).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+        .time_to_live_for_none_backoff(Duration::from_secs(1), Duration::from_secs(5))
+        .build();
+    let reload = async |id| -> Result<Option<u64>> {
+        cache.invalidate(&id).await;
+        cache.try_get(id).await?;
+        Ok(match cache.inspect(&id).await {
+            EntryState::NotFound { expires_in } => {
+                expires_in.map(|ttl| ttl.as_secs_f64().round() as u64)
+            }
+            _ => None,
+        })
+    };
+
+    // consecutive misses are cached twice as long each time, up to the cap
+    let mut ttls = Vec::new();
+    for _ in 0..5 {
+        ttls.push(reload(1).await?);
+    }
+    assert_eq!(ttls, [Some(1), Some(2), Some(4), Some(5), Some(5)]);
+    assert_eq!(reload(2).await?, Some(1));
+
+    // the streak resets when the row appears
+    let insert = "INSERT INTO cakes(id, name, fruit_id) VALUES (1, 'lemon', NULL)";
+    sqlx::query(insert).execute(&pool).await?;
+    assert_eq!(reload(1).await?, None);
+    sqlx::query("DELETE FROM cakes").execute(&pool).await?;
+    assert_eq!(reload(1).await?, Some(1));
+
+    // jittered replacements extend the streak once
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes")
+        .time_to_live_for_none_backoff(Duration::from_secs(1), Duration::from_secs(60))
+        .ttl_jitter(0.01)
+        .build();
+    for _ in 0..3 {
+        cache.insert(1, None).await;
+    }
+    let EntryState::NotFound { expires_in } = cache.inspect(&1).await else {
+        panic!("the absence of the row is not cached");
+    };
+    assert_eq!(expires_in.map(|ttl| ttl.as_secs_f64().round()), Some(4.0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn absent_keys_are_filtered() -> Result<()> {
+    let (pool, cakes) = bakery(0..100).await?;
+    let cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(1024, pool.clone(), "cakes")
+        .existence_filter(0.01)
+        .build();
+
+    // lookups are not filtered until the filter is built
+    assert_eq!(cache.try_get(-1).await?, None);
+    assert_eq!(cache.stats().misses, 1);
+    assert_eq!(cache.rebuild_filter().await?, 100);
+    cache.invalidate_all();
+
+    // absent keys are answered without querying nor caching them, present keys are loaded
+    for id in 1000..2000 {
+        assert_eq!(cache.try_get(id).await?, None);
+    }
+    for cake in &cakes {
+        assert_eq!(
+            *cake,
+            cache.try_get(cake.id).await?.expect("cake is missing.")
+        );
+    }
+    cache.run_pending_tasks().await;
+    let stats = cache.stats();
+    assert_eq!(stats.hits, 0);
+    assert!(stats.filtered > 1000 - 30, "{stats:?}");
+    assert_eq!(stats.filtered + stats.misses, 1 + 1000 + 100);
+    assert!(stats.entry_count < 100 + 30, "{stats:?}");
+
+    // inserted keys are added to the filter
+    cache.insert(500, Some(Arc::new(Cake::new(500)))).await;
+    cache.invalidate(&500).await;
+    sqlx::query("INSERT INTO cakes(id, name, fruit_id) VALUES (500, 'lemon', NULL)")
+        .execute(&pool)
+        .await?;
+    assert!(cache.try_get(500).await?.is_some());
+
+    // a new generation disables the filter until it is rebuilt
+    sqlx::query("INSERT INTO cakes(id, name, fruit_id) VALUES (501, 'lime', NULL)")
+        .execute(&pool)
+        .await?;
+    cache.bump_generation();
+    assert!(cache.try_get(501).await?.is_some());
+    assert_eq!(cache.rebuild_filter().await?, 102);
+    Ok(())
+}
+
+#[tokio::test]
+async fn loads_are_limited() -> Result<()> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await?;
+    sqlx::query("CREATE TABLE cakes (id INTEGER PRIMARY KEY, name TEXT, fruit_id BIGINT)")
+        .execute(&pool)
+        .await?;
+    let cache: Arc<SqliteCache<i64, Cake>> = Arc::new(
+        SqliteCacheBuilder::new(16, pool.clone(), "cakes")
+            .max_concurrent_loads(1)
+            .max_queued_loads(1)
+            .build(),
+    );
+    let lookup = |id, priority| {
+        let cache = Arc::clone(&cache);
+        tokio::spawn(async move { cache.try_get_with_priority(id, priority).await })
+    };
+
+    // the only connection is taken, so the first load holds the only slot
+    let conn = pool.acquire().await?;
+    let running = lookup(0, LoadPriority::Normal);
+    sleep(Duration::from_millis(50)).await;
+    let low = lookup(1, LoadPriority::Low);
+    sleep(Duration::from_millis(50)).await;
+
+    // a load of a higher priority sheds the waiting load, and the others are shed right away
+    let normal = lookup(2, LoadPriority::Normal);
+    let shed = low.await?.expect_err("the load is not shed");
+    assert!(matches!(*shed, LoadError::Overloaded));
+    let error = cache.try_get(3).await.expect_err("the load is not shed");
+    assert!(matches!(*error, LoadError::Overloaded));
+    assert_eq!(cache.get(&3).await, None);
+
+    // the slot is handed over when the running load finishes
+    drop(conn);
+    assert_eq!(running.await??, None);
+    assert_eq!(normal.await??, None);
+    assert_eq!(cache.try_get(3).await?, None);
+    Ok(())
+}
+
+/// Marks a connection as holding a fake advisory lock, with a setting of the connection that
+/// survives the rollback of its transaction, like a session-level lock.
+async fn hold(conn: &mut SqliteConnection) -> sqlx::Result<bool> {
+    sqlx::Executor::execute(conn, "PRAGMA busy_timeout = 1234").await?;
+    Ok(true)
+}
+
+/// Releases the fake advisory lock of a connection, or waits forever if `stall` is set.
+async fn unhold(conn: &mut SqliteConnection, stall: bool) -> sqlx::Result<()> {
+    if stall {
+        std::future::pending::<()>().await;
+    }
+    sqlx::Executor::execute(conn, "PRAGMA busy_timeout = 5000").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn advisory_locks_wrap_loads() -> Result<()> {
+    // the IDs of the locks do not depend on the platform or the toolchain
+    let id = |key: i64| AdvisoryLock::<Sqlite>::id(Some("cakes"), &key);
+    assert_eq!(id(1), -3623019626722576910);
+    assert_ne!(id(2), id(1));
+
+    // the lock of a key is held around its query
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let stall = Arc::new(AtomicBool::new(false));
+    let leaked = Arc::new(AtomicBool::new(false));
+    let pool = SqlitePoolOptions::new()
+        .after_release({
+            let leaked = Arc::clone(&leaked);
+            move |conn, _| {
+                let leaked = Arc::clone(&leaked);
+                Box::pin(async move {
+                    let timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout")
+                        .fetch_one(&mut *conn)
+                        .await?;
+                    leaked.fetch_or(timeout == 1234, Ordering::SeqCst);
+                    Ok(true)
+                })
+            }
+        })
+        .connect("sqlite::memory:")
+        .await?;
+    // keeps the in-memory database alive while the connections of the loads are closed
+    let _conn = pool.acquire().await?;
+    sqlx::query("CREATE TABLE cakes (id INTEGER PRIMARY KEY, name TEXT, fruit_id BIGINT)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO cakes VALUES (1, 'carrot', NULL), (2, 'lemon', NULL)")
+        .execute(&pool)
+        .await?;
+    let mut cache: SqliteCache<i64, Cake> = SqliteCacheBuilder::new(16, pool, "cakes")
+        .name("cakes")
+        .build();
+    cache.advisory_lock = Some(AdvisoryLock {
+        acquire: Box::new({
+            let calls = Arc::clone(&calls);
+            move |conn, id| {
+                calls.lock().unwrap().push(("acquire", id));
+                Box::pin(hold(conn))
+            }
+        }),
+        release: Box::new({
+            let (calls, stall) = (Arc::clone(&calls), Arc::clone(&stall));
+            move |conn, id| {
+                calls.lock().unwrap().push(("release", id));
+                Box::pin(unhold(conn, stall.load(Ordering::SeqCst)))
+            }
+        }),
+        timeout: Duration::from_secs(1),
+    });
+    assert!(cache.try_get(1).await?.is_some());
+    assert_eq!(
+        *calls.lock().unwrap(),
+        [("acquire", id(1)), ("release", id(1))]
+    );
+
+    // a load cancelled while holding the lock does not return its connection to the pool
+    stall.store(true, Ordering::SeqCst);
+    let load = tokio::time::timeout(Duration::from_millis(50), cache.try_get(2));
+    assert!(load.await.is_err());
+    sleep(Duration::from_millis(50)).await;
+    stall.store(false, Ordering::SeqCst);
+    assert!(cache.try_get(2).await?.is_some());
+    sleep(Duration::from_millis(50)).await;
+    assert!(!leaked.load(Ordering::SeqCst));
+
+    // a lock held elsewhere is retried without holding a connection, then skipped
+    let attempts = Arc::new(AtomicUsize::new(0));
+    cache.advisory_lock = Some(AdvisoryLock {
+        acquire: Box::new({
+            let attempts = Arc::clone(&attempts);
+            move |_, _| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async { Ok(false) })
+            }
+        }),
+        release: Box::new(|_, _| panic!("released a lock that was not acquired")),
+        timeout: Duration::from_millis(200),
+    });
+    cache.invalidate(&1).await;
+    let cache = Arc::new(cache);
+    let start = tokio::time::Instant::now();
+    let load = tokio::spawn({
+        let cache = Arc::clone(&cache);
+        async move { cache.try_get(1).await.map(|cake| cake.is_some()) }
+    });
+    sleep(Duration::from_millis(100)).await;
+    let pool = &cache.pool;
+    assert_eq!(pool.size() as usize - pool.num_idle(), 1);
+    assert!(load.await??);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(attempts.load(Ordering::SeqCst) > 2);
+    Ok(())
+}
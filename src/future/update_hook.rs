@@ -0,0 +1,149 @@
+use std::{
+    cell::Cell,
+    ffi::{CStr, c_char, c_int, c_void},
+    sync::{Arc, Mutex},
+};
+
+use libsqlite3_sys::{
+    SQLITE_DELETE, SQLITE_DROP_TABLE, SQLITE_DROP_TEMP_TABLE, SQLITE_DROP_TEMP_VIEW,
+    SQLITE_DROP_VIEW, SQLITE_DROP_VTABLE, SQLITE_IGNORE, SQLITE_OK, sqlite3_set_authorizer,
+};
+use sqlx::sqlite::SqliteConnection;
+
+/// Notifies a subscribed cache of the change of a row, and returns whether the cache is alive.
+type Subscriber = Box<dyn Fn(&str, &str, i64) -> bool + Send + Sync>;
+
+/// A row changed by a transaction, identified by its database, table and rowid.
+type Row = (Box<str>, Box<str>, i64);
+
+/// Reports the rows changed through SQLite connections to the `SqliteCache`s caching them.
+///
+/// The hook is attached to the connections of a pool when they are opened, and caches
+/// subscribe to it with [`RowCacheBuilder::invalidate_on_update`](crate::future::RowCacheBuilder::invalidate_on_update).
+/// The rows inserted, updated or deleted by a transaction are reported when it commits, and
+/// their cached entries are invalidated by the next lookup, so that a lookup following a write
+/// never returns the previous row. A lookup racing with the commit may still cache it.
+///
+/// Since the pool must exist before the caches are built, the hook is created first:
+///
+/// ```no_run
+/// # use moka_more::future::{SqliteCache, SqliteCacheBuilder, UpdateHook};
+/// # use sqlx::{FromRow, sqlite::SqlitePoolOptions};
+/// # #[derive(FromRow)]
+/// # struct User {
+/// #     id: i64,
+/// # }
+/// # async fn example() -> Result<(), sqlx::Error> {
+/// let hook = UpdateHook::default();
+/// let attach = hook.clone();
+/// let pool = SqlitePoolOptions::new()
+///     .after_connect(move |conn, _| {
+///         let hook = attach.clone();
+///         Box::pin(async move { hook.attach(conn).await })
+///     })
+///     .connect("sqlite::memory:")
+///     .await?;
+/// let cache: SqliteCache<i64, User> = SqliteCacheBuilder::new(1024, pool, "users")
+///     .invalidate_on_update(&hook, Some)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// SQLite does not report the rows of a table emptied at once by a `DELETE` without a `WHERE`
+/// clause (the truncate optimization), so the optimization is disabled on the attached
+/// connections, and such statements delete and report their rows one by one. The changes of
+/// `WITHOUT ROWID` tables are never reported.
+///
+/// Only the changes made through attached connections are reported, so writes from other
+/// processes still go unnoticed until the entries expire.
+#[derive(Clone, Default)]
+pub struct UpdateHook {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl UpdateHook {
+    /// Attaches the hook to a connection, typically from `PoolOptions::after_connect`.
+    ///
+    /// This replaces the update, commit and rollback hooks and the authorizer of the
+    /// connection, if any.
+    pub async fn attach(&self, conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let changed = Arc::new(Mutex::new(Vec::<Row>::new()));
+        let mut handle = conn.lock_handle().await?;
+        // SAFETY: the handle is valid while it is locked, and the authorizer keeps no state
+        // tied to the connection.
+        unsafe {
+            sqlite3_set_authorizer(
+                handle.as_raw_handle().as_ptr(),
+                Some(authorize),
+                std::ptr::null_mut(),
+            );
+        }
+        let rows = Arc::clone(&changed);
+        handle.set_update_hook(move |update| {
+            let row = (update.database.into(), update.table.into(), update.rowid);
+            rows.lock().unwrap().push(row);
+        });
+        let rows = Arc::clone(&changed);
+        let subscribers = Arc::clone(&self.subscribers);
+        handle.set_commit_hook(move || {
+            let rows = std::mem::take(&mut *rows.lock().unwrap());
+            if !rows.is_empty() {
+                let mut subscribers = subscribers.lock().unwrap();
+                for (database, table, rowid) in rows {
+                    subscribers.retain(|notify| notify(&database, &table, rowid));
+                }
+            }
+            true
+        });
+        handle.set_rollback_hook(move || changed.lock().unwrap().clear());
+        Ok(())
+    }
+
+    /// Subscribes to the changed rows, given by their database, table and rowid, until
+    /// `notify` returns `false`.
+    pub(crate) fn subscribe(
+        &self,
+        notify: impl Fn(&str, &str, i64) -> bool + Send + Sync + 'static,
+    ) {
+        self.subscribers.lock().unwrap().push(Box::new(notify));
+    }
+}
+
+thread_local! {
+    /// Whether the previous action authorized on the thread dropped a table or a view.
+    static DROPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Authorizes every action, but disables the truncate optimization of the `DELETE`s of the
+/// tables, so that their rows are reported to the update hook.
+///
+/// A `DROP TABLE` also authorizes the deletion of the table right after the drop itself,
+/// which must be allowed as is, as ignoring it would skip the drop. So must the deletions
+/// from the schema tables.
+unsafe extern "C" fn authorize(
+    _data: *mut c_void,
+    action: c_int,
+    table: *const c_char,
+    _: *const c_char,
+    _: *const c_char,
+    _: *const c_char,
+) -> c_int {
+    let dropping = DROPPING.replace(matches!(
+        action,
+        SQLITE_DROP_TABLE
+            | SQLITE_DROP_TEMP_TABLE
+            | SQLITE_DROP_VIEW
+            | SQLITE_DROP_TEMP_VIEW
+            | SQLITE_DROP_VTABLE
+    ));
+    if action != SQLITE_DELETE || dropping || table.is_null() {
+        return SQLITE_OK;
+    }
+    // SAFETY: SQLite passes the name of the table as a NUL-terminated string.
+    let table = unsafe { CStr::from_ptr(table) };
+    match table.to_bytes().starts_with(b"sqlite_") {
+        true => SQLITE_OK,
+        false => SQLITE_IGNORE,
+    }
+}
@@ -1 +1,11 @@
+//! Extends the moka crate with additional functionality, primarily for database interactions.
+//!
+//! # Breaking changes
+//!
+//! The lookups of [`RowCache`](future::RowCache) that load rows, such as
+//! [`try_get`](future::RowCache::try_get), fail with `Arc<`[`LoadError`](future::LoadError)`>`
+//! instead of `Arc<sqlx::Error>`, even without a limit on the concurrent loads. The errors of
+//! the database are wrapped in [`LoadError::Database`](future::LoadError::Database), while
+//! shed loads fail with [`LoadError::Overloaded`](future::LoadError::Overloaded).
+
 pub mod future;